@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE "crawl_attempts"(
+	"experience_id" INT4 NOT NULL PRIMARY KEY,
+	"outcome" VARCHAR(32) NOT NULL,
+	"attempt_count" INT4 NOT NULL,
+	"last_error" TEXT,
+	"first_attempt_at" TIMESTAMP NOT NULL,
+	"last_attempt_at" TIMESTAMP NOT NULL
+);
+
+CREATE INDEX "crawl_attempts_outcome_idx" ON "crawl_attempts"("outcome");
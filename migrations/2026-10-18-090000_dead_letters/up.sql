@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE "dead_letters"(
+	"experience_id" INT4 NOT NULL PRIMARY KEY,
+	"attempts" INT4 NOT NULL,
+	"last_error" TEXT,
+	"created_at" TIMESTAMP NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+CREATE INDEX "experiences_updated_at_idx" ON "experiences"("updated_at");
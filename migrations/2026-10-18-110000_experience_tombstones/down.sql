@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "experiences" DROP COLUMN IF EXISTS "deleted_at";
+ALTER TABLE "experiences" DROP COLUMN IF EXISTS "last_seen_at";
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE "experiences" ADD COLUMN "last_seen_at" TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC');
+ALTER TABLE "experiences" ADD COLUMN "deleted_at" TIMESTAMP;
+
+UPDATE "experiences" SET "last_seen_at" = "updated_at";
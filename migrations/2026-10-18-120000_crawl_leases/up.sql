@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE "crawl_leases"(
+	"range_start" INT4 NOT NULL PRIMARY KEY,
+	"range_end" INT4 NOT NULL,
+	"next_code" INT4 NOT NULL,
+	"worker" VARCHAR(64),
+	"leased_until" TIMESTAMP,
+	"completed_at" TIMESTAMP
+);
+
+CREATE INDEX "crawl_leases_open_idx" ON "crawl_leases"("range_start") WHERE "completed_at" IS NULL;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE "dispatched_work"(
+	"code" INT4 NOT NULL PRIMARY KEY,
+	"kind" VARCHAR(16) NOT NULL,
+	"attempt" INT4 NOT NULL,
+	"saved_at" TIMESTAMP NOT NULL
+);
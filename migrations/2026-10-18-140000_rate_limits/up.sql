@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE "rate_limits"(
+	"name" VARCHAR(64) NOT NULL PRIMARY KEY,
+	"tokens" FLOAT8 NOT NULL,
+	"updated_at" TIMESTAMP NOT NULL
+);
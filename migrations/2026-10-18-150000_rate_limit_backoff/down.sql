@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "rate_limits" DROP COLUMN IF EXISTS "backoff_until";
+ALTER TABLE "rate_limits" DROP COLUMN IF EXISTS "backoff_level";
+ALTER TABLE "rate_limits" DROP COLUMN IF EXISTS "rate_factor";
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE "rate_limits" ADD COLUMN "rate_factor" FLOAT8 NOT NULL DEFAULT 1;
+ALTER TABLE "rate_limits" ADD COLUMN "backoff_level" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "rate_limits" ADD COLUMN "backoff_until" TIMESTAMP;
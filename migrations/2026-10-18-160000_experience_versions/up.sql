@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE "experience_versions"(
+	"experience_id" INT4 NOT NULL,
+	"version" INT4 NOT NULL,
+	"content_hash" VARCHAR(64) NOT NULL,
+	"playground_name" VARCHAR(255) NOT NULL,
+	"playground_description" TEXT NOT NULL,
+	"playground_data" JSONB NOT NULL,
+	"tags" JSONB NOT NULL,
+	"progression_mode" JSONB NOT NULL,
+	"maps" TEXT[] NOT NULL,
+	"game_sizes" INT4[] NOT NULL,
+	"modes" TEXT[] NOT NULL,
+	"playground_updated_at" TIMESTAMP NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	PRIMARY KEY("experience_id", "version")
+);
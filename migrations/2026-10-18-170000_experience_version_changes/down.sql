@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "experience_versions" DROP COLUMN IF EXISTS "changes";
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE "experience_versions" ADD COLUMN "changes" JSONB;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE "experience_rotations"(
+	"experience_id" INT4 NOT NULL,
+	"position" INT4 NOT NULL,
+	"map_name" TEXT,
+	"mode" TEXT,
+	"game_size" INT4,
+	PRIMARY KEY("experience_id", "position")
+);
+
+CREATE INDEX "experience_rotations_map_idx" ON "experience_rotations"("map_name", "mode", "game_size");
+
+INSERT INTO "experience_rotations"("experience_id", "position", "map_name", "mode", "game_size")
+SELECT "experiences"."experience_id", "rotation"."position" - 1, "rotation"."map_name", "rotation"."mode", "rotation"."game_size"
+FROM "experiences",
+	UNNEST("experiences"."maps", "experiences"."modes", "experiences"."game_sizes")
+	WITH ORDINALITY AS "rotation"("map_name", "mode", "game_size", "position");
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "experience_tags";
+DROP TABLE IF EXISTS "tags";
@@ -0,0 +1,31 @@
+-- Your SQL goes here
+CREATE TABLE "tags"(
+	"id" SERIAL NOT NULL PRIMARY KEY,
+	"name" VARCHAR(255) NOT NULL UNIQUE,
+	"category" TEXT,
+	"first_seen" TIMESTAMP NOT NULL
+);
+
+CREATE TABLE "experience_tags"(
+	"experience_id" INT4 NOT NULL,
+	"tag_id" INT4 NOT NULL REFERENCES "tags"("id"),
+	PRIMARY KEY("experience_id", "tag_id")
+);
+
+CREATE INDEX "experience_tags_tag_id_idx" ON "experience_tags"("tag_id");
+
+INSERT INTO "tags"("name", "category", "first_seen")
+SELECT "found"."tag_name", MIN("found"."category"), MIN("found"."created_at")
+FROM (
+	SELECT COALESCE("tag"->>'tagId', "tag"->>'tag_id') AS "tag_name", "tag"->>'category' AS "category", "experiences"."created_at"
+	FROM "experiences"
+	CROSS JOIN LATERAL JSONB_ARRAY_ELEMENTS(CASE WHEN JSONB_TYPEOF("experiences"."tags") = 'array' THEN "experiences"."tags" ELSE '[]'::JSONB END) AS "tag"
+) AS "found"
+WHERE "found"."tag_name" IS NOT NULL
+GROUP BY "found"."tag_name";
+
+INSERT INTO "experience_tags"("experience_id", "tag_id")
+SELECT DISTINCT "experiences"."experience_id", "tags"."id"
+FROM "experiences"
+CROSS JOIN LATERAL JSONB_ARRAY_ELEMENTS(CASE WHEN JSONB_TYPEOF("experiences"."tags") = 'array' THEN "experiences"."tags" ELSE '[]'::JSONB END) AS "tag"
+JOIN "tags" ON "tags"."name" = COALESCE("tag"->>'tagId', "tag"->>'tag_id');
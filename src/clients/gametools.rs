@@ -0,0 +1,67 @@
+use std::{env, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::StatusCode;
+
+use crate::{
+    clients::playground_source::PlaygroundSource, connectors::postgres::models::Experience,
+    error::Result, experience_code::ExperienceCode,
+};
+
+const DEFAULT_GAMETOOLS_URL: &str = "https://api.gametools.network";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Playgrounds from the gametools API, no EA account needed
+pub struct GametoolsClient {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl GametoolsClient {
+    /// Read GAMETOOLS_URL, with a default
+    pub fn from_env() -> Self {
+        let base_url = env::var("GAMETOOLS_URL").unwrap_or(DEFAULT_GAMETOOLS_URL.to_string());
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl PlaygroundSource for GametoolsClient {
+    fn name(&self) -> &'static str {
+        "gametools"
+    }
+
+    async fn fetch(&mut self, e_code: &ExperienceCode) -> Result<Option<Experience>> {
+        let code: String = e_code.clone().into();
+        let response = self
+            .http
+            .get(format!("{}/bf2042/playground/", self.base_url))
+            .query(&[
+                ("experiencecode", code.as_str()),
+                ("blockydata", "false"),
+                ("return_ownername", "false"),
+                ("lang", "en-us"),
+            ])
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let playground: serde_json::Value =
+            serde_json::from_str(&response.error_for_status()?.text().await?)?;
+        if playground.get("originalPlayground").is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Experience::init_gametools(
+            e_code.clone(),
+            playground,
+        )?))
+    }
+}
@@ -1 +1,4 @@
+pub mod gametools;
+pub mod playground_source;
+pub mod recording;
 pub mod standalone_client;
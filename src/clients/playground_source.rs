@@ -0,0 +1,94 @@
+use std::env;
+
+use async_trait::async_trait;
+
+use crate::{
+    clients::{gametools::GametoolsClient, standalone_client::StandaloneClient},
+    connectors::{
+        health::Health,
+        mongo::lib::MongoClient,
+        postgres::{lib::PostgresClient, models::Experience},
+    },
+    error::{ExplorerError, Result},
+    experience_code::ExperienceCode,
+};
+
+/// Somewhere experiences can be fetched from
+#[async_trait]
+pub trait PlaygroundSource: Send {
+    /// Used in logs
+    fn name(&self) -> &'static str;
+
+    /// Fetch the experience of a code, None if the code has no playground
+    async fn fetch(&mut self, e_code: &ExperienceCode) -> Result<Option<Experience>>;
+}
+
+/// Sources tried in order, the next one is used when a source fails.
+/// Throttling isn't passed on to the next source, the fleet backs off instead
+pub struct FallbackSource {
+    sources: Vec<Box<dyn PlaygroundSource>>,
+}
+
+#[async_trait]
+impl PlaygroundSource for FallbackSource {
+    fn name(&self) -> &'static str {
+        "fallback"
+    }
+
+    async fn fetch(&mut self, e_code: &ExperienceCode) -> Result<Option<Experience>> {
+        // the error of the primary source is the one that is reported
+        let mut first_error = None;
+        for source in self.sources.iter_mut() {
+            match source.fetch(e_code).await {
+                Ok(experience) => return Ok(experience),
+                Err(e @ ExplorerError::Throttled(_)) => return Err(e),
+                Err(e) => {
+                    log::warn!("{} failed, trying the next source: {}", source.name(), e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| ExplorerError::other("no playground sources configured")))
+    }
+}
+
+/// Connect the sources of PLAYGROUND_SOURCES, a comma separated list of kingston and
+/// gametools with the primary first. Defaults to kingston
+pub async fn connect(
+    health: &Health,
+    worker: &str,
+    mongo_client: MongoClient,
+    db_client: PostgresClient,
+) -> Result<Box<dyn PlaygroundSource>> {
+    let names = env::var("PLAYGROUND_SOURCES").unwrap_or("kingston".to_string());
+
+    let mut sources: Vec<Box<dyn PlaygroundSource>> = vec![];
+    for name in names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        match name {
+            "kingston" => {
+                let mut client = StandaloneClient::new(health, worker);
+                client
+                    .connect(mongo_client.clone(), db_client.clone())
+                    .await?;
+                sources.push(Box::new(client));
+            }
+            "gametools" => sources.push(Box::new(GametoolsClient::from_env())),
+            other => {
+                return Err(ExplorerError::other(format!(
+                    "unknown playground source {}",
+                    other
+                )))
+            }
+        }
+    }
+
+    match sources.len() {
+        0 => Err(ExplorerError::other("PLAYGROUND_SOURCES is empty")),
+        1 => Ok(sources.remove(0)),
+        _ => Ok(Box::new(FallbackSource { sources })),
+    }
+}
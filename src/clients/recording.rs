@@ -0,0 +1,232 @@
+use std::{
+    collections::VecDeque,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Lines, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use grpc_rust::modules::communitygames::PlaygroundInfoResponse;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clients::{playground_source::PlaygroundSource, standalone_client::into_experience},
+    connectors::postgres::models::Experience,
+    error::{ExplorerError, Result},
+    experience_code::ExperienceCode,
+};
+
+/// Responses written to the file at once
+const BATCH_SIZE: usize = 100;
+/// Smaller batches are written after this long, a crash only loses the last few seconds
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A line of the recordings, `R` is borrowed while recording
+#[derive(Serialize, Deserialize)]
+struct Recording<R> {
+    code: String,
+    experience_id: i32,
+    /// Seconds since epoch
+    recorded_at: i64,
+    response: R,
+}
+
+/// Responses that weren't written yet
+struct Batch {
+    path: PathBuf,
+    lines: Vec<String>,
+}
+
+impl Batch {
+    /// Append the buffered responses as one gzip member
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.lines.is_empty() {
+            return Ok(());
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        for line in self.lines.drain(..) {
+            encoder.write_all(line.as_bytes())?;
+            encoder.write_all(b"\n")?;
+        }
+        encoder.finish()?;
+        Ok(())
+    }
+}
+
+/// Appends raw playground responses to gzipped NDJSON, so they can be parsed again later.
+///
+/// Every batch is its own gzip member, written when it's full, every FLUSH_INTERVAL
+/// and when the recorder is dropped on shutdown.
+pub struct Recorder {
+    batch: Arc<Mutex<Batch>>,
+}
+
+impl Recorder {
+    /// Record into a new file in RECORD_DIR, None when it isn't set.
+    /// Has to be called from within the runtime
+    pub fn from_env(worker: &str) -> anyhow::Result<Option<Self>> {
+        let dir = match env::var("RECORD_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => return Ok(None),
+        };
+        fs::create_dir_all(&dir)?;
+        // starts with the time so the files replay in the order they were recorded
+        let path = dir.join(format!(
+            "{}-{}.ndjson.gz",
+            Utc::now().format("%Y%m%d-%H%M%S"),
+            worker
+        ));
+        log::info!("recording playground responses to {}", path.display());
+        let batch = Arc::new(Mutex::new(Batch {
+            path,
+            lines: vec![],
+        }));
+
+        // stops once the recorder is dropped
+        let pending = Arc::downgrade(&batch);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let batch = match pending.upgrade() {
+                    Some(batch) => batch,
+                    None => break,
+                };
+                let flushed = batch.lock().unwrap().flush();
+                if let Err(e) = flushed {
+                    log::warn!("couldn't write recorded responses: {:#}", e);
+                }
+            }
+        });
+        Ok(Some(Self { batch }))
+    }
+
+    pub fn record(
+        &mut self,
+        e_code: &ExperienceCode,
+        response: &PlaygroundInfoResponse,
+    ) -> anyhow::Result<()> {
+        let recording = Recording {
+            code: e_code.clone().into(),
+            experience_id: e_code.to_usize()? as i32,
+            recorded_at: Utc::now().timestamp(),
+            response,
+        };
+        let mut batch = self.batch.lock().unwrap();
+        batch.lines.push(serde_json::to_string(&recording)?);
+        if batch.lines.len() >= BATCH_SIZE {
+            batch.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.batch.lock().unwrap().flush()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::warn!("couldn't write recorded responses: {:#}", e);
+        }
+    }
+}
+
+/// Reads the recordings back one response at a time, in the order they were recorded
+pub struct ReplaySource {
+    files: VecDeque<PathBuf>,
+    lines: Option<Lines<BufReader<MultiGzDecoder<File>>>>,
+    current: Option<Recording<PlaygroundInfoResponse>>,
+}
+
+impl ReplaySource {
+    /// Replay every .ndjson.gz file in `dir`
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.to_string_lossy().ends_with(".ndjson.gz"))
+            .collect();
+        files.sort();
+        Ok(Self {
+            files: files.into(),
+            lines: None,
+            current: None,
+        })
+    }
+
+    /// When the current response was recorded
+    pub fn recorded_at(&self) -> Option<NaiveDateTime> {
+        self.current.as_ref().map(|recording| {
+            chrono::DateTime::from_timestamp(recording.recorded_at, 0)
+                .unwrap_or_default()
+                .naive_utc()
+        })
+    }
+
+    /// Move to the next recorded response, returns its code or None at the end
+    pub fn advance(&mut self) -> anyhow::Result<Option<i32>> {
+        loop {
+            let lines = match self.lines.as_mut() {
+                Some(lines) => lines,
+                None => match self.files.pop_front() {
+                    Some(path) => {
+                        log::info!("replaying {}", path.display());
+                        self.lines
+                            .insert(BufReader::new(MultiGzDecoder::new(File::open(path)?)).lines())
+                    }
+                    None => return Ok(None),
+                },
+            };
+            match lines.next() {
+                Some(line) => {
+                    let line = line?;
+                    if line.is_empty() {
+                        continue;
+                    }
+                    let recording: Recording<PlaygroundInfoResponse> = serde_json::from_str(&line)?;
+                    let code = recording.experience_id;
+                    self.current = Some(recording);
+                    return Ok(Some(code));
+                }
+                None => self.lines = None,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl PlaygroundSource for ReplaySource {
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    /// The response of the current recording, it has to be for `e_code`
+    async fn fetch(&mut self, e_code: &ExperienceCode) -> Result<Option<Experience>> {
+        let code = e_code.to_usize()? as i32;
+        let recorded_at = self.recorded_at().unwrap_or_default();
+        let recording = match self.current.take() {
+            Some(recording) if recording.experience_id == code => recording,
+            _ => {
+                return Err(ExplorerError::other(format!(
+                    "{} is not the current recording",
+                    code
+                )))
+            }
+        };
+        let mut experience = into_experience(e_code, recording.response)?;
+        if let Some(experience) = experience.as_mut() {
+            experience.last_seen_at = recorded_at;
+        }
+        Ok(experience)
+    }
+}
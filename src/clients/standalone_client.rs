@@ -1,65 +1,416 @@
-use crate::{connectors::mongo::lib::MongoClient, experience_code::ExperienceCode};
-use bf_sparta::{cookie_request, sparta_api};
+use crate::{
+    clients::{playground_source::PlaygroundSource, recording::Recorder},
+    connectors::{
+        health::{Health, SessionStats},
+        mongo::{lib::MongoClient, models::BackendCookie},
+        postgres::{lib::PostgresClient, models::Experience},
+    },
+    error::{ExplorerError, Result},
+    experience_code::ExperienceCode,
+    rate_limit::RateLimiter,
+};
+use async_trait::async_trait;
+use bf_sparta::{cookie::Cookie, cookie_request, sparta_api};
+use chrono::{TimeDelta, Utc};
 use dotenvy::dotenv;
 use grpc_rust::{
     grpc::KingstonClient,
     modules::{communitygames::PlaygroundInfoResponse, CommunityGames},
 };
-use std::env;
+use std::{env, time::Duration};
+
+/// Minutes an account that failed to authenticate is left alone
+const DEFAULT_QUARANTINE_MINUTES: i64 = 30;
+
+/// Kingston session of one account
+struct Session {
+    account: String,
+    kingston_client: Option<KingstonClient>,
+    /// Requests of this account, on top of the fleet budget
+    rate_limiter: RateLimiter,
+    stats: SessionStats,
+}
+
+impl Session {
+    fn is_quarantined(&self) -> bool {
+        self.stats
+            .quarantined_until
+            .is_some_and(|until| until > Utc::now().timestamp())
+    }
+}
 
 pub struct StandaloneClient {
-    pub kingston_client: Option<KingstonClient>,
+    sessions: Vec<Session>,
+    /// Session the next request starts looking from
+    next: usize,
+    quarantine: TimeDelta,
+    /// Where the cookies come from, set on connect
+    mongo_client: Option<MongoClient>,
+    /// Keeps the per-account budgets, set on connect
+    db_client: Option<PostgresClient>,
+    /// Budget of the whole fleet, callers take its token before the first attempt
+    fleet: RateLimiter,
+    /// Session age and re-auths are reported on the healthcheck
+    health: Health,
+    /// Stored with refreshed cookies
+    worker: String,
+    /// Keeps the raw responses when RECORD_DIR is set
+    recorder: Option<Recorder>,
 }
 
 impl StandaloneClient {
-    pub async fn connect(&mut self, mut mongo_client: MongoClient) -> anyhow::Result<()> {
+    /// `worker` names this process on the cookies it refreshes
+    pub fn new(health: &Health, worker: &str) -> Self {
+        Self {
+            sessions: vec![],
+            next: 0,
+            quarantine: TimeDelta::minutes(DEFAULT_QUARANTINE_MINUTES),
+            mongo_client: None,
+            db_client: None,
+            fleet: RateLimiter::from_env(),
+            health: health.clone(),
+            worker: worker.to_string(),
+            recorder: None,
+        }
+    }
+
+    /// Authenticate every account of API_BF2042_ACCOUNTS (comma separated, or the single
+    /// API_BF2042_ACCOUNT), fails only when none of them could
+    pub async fn connect(
+        &mut self,
+        mongo_client: MongoClient,
+        db_client: PostgresClient,
+    ) -> Result<()> {
         dotenv().ok();
-        let bf2042_account =
-            env::var("API_BF2042_ACCOUNT").expect("API_BF2042_ACCOUNT must be set");
+        let accounts = env::var("API_BF2042_ACCOUNTS")
+            .or_else(|_| env::var("API_BF2042_ACCOUNT"))
+            .map_err(|_| {
+                ExplorerError::other("API_BF2042_ACCOUNTS or API_BF2042_ACCOUNT must be set")
+            })?;
+        let quarantine_minutes: i64 = env::var("ACCOUNT_QUARANTINE_MINUTES")
+            .ok()
+            .and_then(|minutes| minutes.parse().ok())
+            .unwrap_or(DEFAULT_QUARANTINE_MINUTES);
+
+        self.quarantine = TimeDelta::minutes(quarantine_minutes.max(1));
+        self.recorder = Recorder::from_env(&self.worker)?;
+        self.mongo_client = Some(mongo_client);
+        self.db_client = Some(db_client);
+        self.sessions = accounts
+            .split(',')
+            .map(str::trim)
+            .filter(|account| !account.is_empty())
+            .map(|account| Session {
+                account: account.to_string(),
+                kingston_client: None,
+                rate_limiter: RateLimiter::for_account(account),
+                stats: SessionStats::default(),
+            })
+            .collect();
+
+        let mut last_error = ExplorerError::other("no accounts configured");
+        let mut connected = 0;
+        for index in 0..self.sessions.len() {
+            match self.authenticate(index).await {
+                Ok(_) => connected += 1,
+                Err(e) => last_error = e,
+            }
+        }
+        if connected == 0 {
+            return Err(last_error);
+        }
+        log::info!(
+            "{} of {} kingston accounts authenticated",
+            connected,
+            self.sessions.len()
+        );
+        Ok(())
+    }
+
+    /// Start a new Kingston session with the cookies currently stored in Mongo,
+    /// quarantines the account when it fails
+    async fn authenticate(&mut self, index: usize) -> Result<()> {
+        let result = self.start_session(index).await;
+        let session = &mut self.sessions[index];
+        match &result {
+            Ok(_) => {
+                session.stats.authenticated_at = Utc::now().timestamp();
+                session.stats.quarantined_until = None;
+            }
+            Err(e) => {
+                log::warn!(
+                    "{} failed to authenticate, quarantined for {}m: {:#}",
+                    session.account,
+                    self.quarantine.num_minutes(),
+                    e
+                );
+                session.kingston_client = None;
+                session.stats.quarantined_until = Some((Utc::now() + self.quarantine).timestamp());
+            }
+        }
+        self.health.session(&session.account, session.stats.clone());
+        result
+    }
 
-        let (bf2042_cookie, ea_access_token) = match mongo_client.get_cookies(&bf2042_account).await
-        {
-            Ok(result) => result,
+    /// Authenticate with the stored cookies, when they are rejected they are refreshed
+    /// and written back to Mongo
+    async fn start_session(&mut self, index: usize) -> Result<()> {
+        let session = &mut self.sessions[index];
+        let mongo_client = match self.mongo_client.as_mut() {
+            Some(mongo_client) => mongo_client,
+            None => return Err(ExplorerError::other("not connected")),
+        };
+        let stored = match mongo_client.get_backend_cookie(&session.account).await {
+            Ok(result) => Some(result),
             Err(e) => {
                 log::warn!("Cookie failed, {}", e);
-                (
-                    bf_sparta::cookie::Cookie {
-                        sid: "".to_string(),
-                        remid: "".to_string(),
-                    },
-                    "".to_string(),
-                )
+                None
             }
         };
+        let (bf2042_cookie, ea_access_token) = match stored.clone() {
+            Some(stored) => (
+                stored.clone().into(),
+                stored.ea_access_token.unwrap_or_default(),
+            ),
+            None => (
+                Cookie {
+                    sid: "".to_string(),
+                    remid: "".to_string(),
+                },
+                "".to_string(),
+            ),
+        };
 
-        let session_id = match self.kingston_client.clone() {
+        let session_id = match session.kingston_client.clone() {
             Some(client) => client.session_id,
             None => "".to_string(),
         };
 
-        let mut kingston_client = KingstonClient::new(session_id.clone()).await?;
-        match kingston_client
-            .ea_desktop_auth(bf2042_cookie, ea_access_token)
-            .await
-        {
-            Ok(_) => {}
-            Err(e) => anyhow::bail!("kingston session failed: {:#?}", e),
+        let error = match desktop_auth(&session_id, bf2042_cookie.clone(), ea_access_token).await {
+            Ok(kingston_client) => {
+                session.kingston_client = Some(kingston_client);
+                return Ok(());
+            }
+            Err(e) => e,
         };
+        let stored = match stored {
+            Some(stored) => stored,
+            None => return Err(error),
+        };
+
+        log::warn!(
+            "stored cookies of {} rejected, refreshing them: {:#}",
+            session.account,
+            error
+        );
+        let (bf2042_cookie, ea_access_token) = refresh_cookies(bf2042_cookie).await?;
+        let kingston_client =
+            desktop_auth(&session_id, bf2042_cookie.clone(), ea_access_token.clone()).await?;
+        session.kingston_client = Some(kingston_client);
 
-        self.kingston_client = Some(kingston_client);
+        save_cookies(
+            mongo_client,
+            &session.account,
+            &bf2042_cookie,
+            ea_access_token,
+            &self.worker,
+            &stored,
+        )
+        .await;
         Ok(())
     }
 
+    /// Next session that is not quarantined and has budget left, waits for the first
+    /// budget when they are all spent or for the first quarantine to end
+    async fn next_session(&mut self) -> Result<usize> {
+        loop {
+            let mut wait: Option<Duration> = None;
+            let mut quarantined_until: Option<i64> = None;
+            for offset in 0..self.sessions.len() {
+                let index = (self.next + offset) % self.sessions.len();
+                let session = &self.sessions[index];
+                if session.is_quarantined() {
+                    let until = session.stats.quarantined_until.unwrap_or_default();
+                    quarantined_until =
+                        Some(quarantined_until.map_or(until, |first| first.min(until)));
+                    continue;
+                }
+                let db_client = match self.db_client.as_mut() {
+                    Some(db_client) => db_client,
+                    None => return Err(ExplorerError::other("not connected")),
+                };
+                match session.rate_limiter.try_acquire(db_client) {
+                    None => {
+                        self.next = index + 1;
+                        return Ok(index);
+                    }
+                    Some(until) => wait = Some(wait.map_or(until, |wait| wait.min(until))),
+                }
+            }
+
+            match (wait, quarantined_until) {
+                (Some(wait), _) => tokio::time::sleep(wait).await,
+                (None, Some(until)) => {
+                    let wait = Duration::from_secs((until - Utc::now().timestamp()).max(1) as u64);
+                    log::warn!("all kingston accounts are quarantined, waiting {:?}", wait);
+                    tokio::time::sleep(wait).await;
+                }
+                (None, None) => return Err(ExplorerError::other("no kingston accounts")),
+            }
+        }
+    }
+
+    /// Fetch a playground on the next healthy session. When the session expired it's
+    /// renewed and the request retried once, an account that can't renew is quarantined
     pub async fn get_playground(
+        &mut self,
+        e_code: &ExperienceCode,
+    ) -> Result<PlaygroundInfoResponse> {
+        loop {
+            let index = self.next_session().await?;
+            if self.sessions[index].kingston_client.is_none()
+                && self.authenticate(index).await.is_err()
+            {
+                continue;
+            }
+
+            let mut result = self.fetch_playground(index, e_code).await;
+            if let Err(e @ ExplorerError::Unauthorized(_)) = &result {
+                log::warn!(
+                    "kingston session of {} rejected, re-authenticating: {}",
+                    self.sessions[index].account,
+                    e
+                );
+                self.sessions[index].stats.reauths += 1;
+                if self.authenticate(index).await.is_err() {
+                    continue;
+                }
+                // the retry is another request, on the budget of the account and the fleet
+                if let Some(db_client) = self.db_client.as_mut() {
+                    self.fleet.acquire(db_client).await;
+                    self.sessions[index].rate_limiter.acquire(db_client).await;
+                }
+                result = self.fetch_playground(index, e_code).await;
+            }
+
+            let session = &self.sessions[index];
+            if let Some(db_client) = self.db_client.as_mut() {
+                match &result {
+                    Err(ExplorerError::Throttled(_)) => session.rate_limiter.throttled(db_client),
+                    Err(_) => {}
+                    Ok(_) => session.rate_limiter.succeeded(db_client),
+                }
+            }
+            return result;
+        }
+    }
+
+    async fn fetch_playground(
         &self,
+        index: usize,
         e_code: &ExperienceCode,
-    ) -> Result<PlaygroundInfoResponse, anyhow::Error> {
-        match &self.kingston_client {
+    ) -> Result<PlaygroundInfoResponse> {
+        match &self.sessions[index].kingston_client {
             Some(kingston_client) => {
                 CommunityGames::get_shared_playground_v2(kingston_client, e_code.clone().into())
                     .await
+                    .map_err(ExplorerError::from_kingston)
             }
-            None => anyhow::bail!("no kingston client available"),
+            None => Err(ExplorerError::Unauthorized(
+                "no kingston session".to_string(),
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl PlaygroundSource for StandaloneClient {
+    fn name(&self) -> &'static str {
+        "kingston"
+    }
+
+    async fn fetch(&mut self, e_code: &ExperienceCode) -> Result<Option<Experience>> {
+        let response = match self.get_playground(e_code).await {
+            Ok(response) => response,
+            Err(ExplorerError::NotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.record(e_code, &response) {
+                log::warn!(
+                    "couldn't record the response of {}: {:#}",
+                    <&str>::from(e_code),
+                    e
+                );
+            }
+        }
+        into_experience(e_code, response)
+    }
+}
+
+/// Parse a playground response, None if the code has no playground
+pub fn into_experience(
+    e_code: &ExperienceCode,
+    response: PlaygroundInfoResponse,
+) -> Result<Option<Experience>> {
+    match response.playground {
+        Some(playground) => Ok(Some(Experience::init_standalone(
+            e_code.clone(),
+            playground,
+        )?)),
+        None => Ok(None),
+    }
+}
+
+async fn desktop_auth(
+    session_id: &str,
+    bf2042_cookie: Cookie,
+    ea_access_token: String,
+) -> Result<KingstonClient> {
+    let mut kingston_client = KingstonClient::new(session_id.to_string())
+        .await
+        .map_err(ExplorerError::from_kingston)?;
+    match kingston_client
+        .ea_desktop_auth(bf2042_cookie, ea_access_token)
+        .await
+    {
+        Ok(_) => Ok(kingston_client),
+        Err(e) => Err(ExplorerError::Unauthorized(format!(
+            "kingston session failed: {:#?}",
+            e
+        ))),
+    }
+}
+
+/// Get a new sid with the remid and a new access token with that
+async fn refresh_cookies(bf2042_cookie: Cookie) -> Result<(Cookie, String)> {
+    let refresh = async {
+        let bf2042_cookie = cookie_request::request_cookie(bf2042_cookie).await?;
+        let ea_access_token = sparta_api::get_token(bf2042_cookie.clone()).await?;
+        anyhow::Ok((bf2042_cookie, ea_access_token))
+    };
+    refresh
+        .await
+        .map_err(|e| ExplorerError::Unauthorized(format!("couldn't refresh cookies: {:#}", e)))
+}
+
+/// Share refreshed cookies with the other services, unless one of them stored newer ones
+async fn save_cookies(
+    mongo_client: &mut MongoClient,
+    account: &str,
+    bf2042_cookie: &Cookie,
+    ea_access_token: String,
+    worker: &str,
+    previous: &BackendCookie,
+) {
+    match mongo_client
+        .push_new_cookies(account, bf2042_cookie, ea_access_token, worker, previous)
+        .await
+    {
+        Ok(result) if result.matched_count == 0 => {
+            log::info!("newer cookies of {} already stored, kept them", account)
         }
+        Ok(_) => log::info!("stored refreshed cookies of {}", account),
+        Err(e) => log::warn!("couldn't store refreshed cookies of {}: {}", account, e),
     }
 }
@@ -1,36 +1,73 @@
 use core::str;
-use std::env;
+use std::{env, time::Duration};
 
 use base64::{prelude::BASE64_STANDARD, Engine};
+
+use super::health::{Backoff, Health};
+use crate::error::{ExplorerError, Result};
 use lapin::{
     options::*,
     tcp::{OwnedIdentity, OwnedTLSConfig},
-    types::FieldTable,
-    BasicProperties, Channel, Connection, ConnectionProperties, Consumer, Result,
+    types::{FieldTable, LongString},
+    BasicProperties, Channel, Connection, ConnectionProperties, Consumer,
 };
 
-async fn get_tls_config() -> OwnedTLSConfig {
-    let client_cert_and_key = env::var("AMQPS_CERT_CLIENT").expect("AMQPS_CERT_CLIENT wasn't set");
-    let cert_chain = env::var("AMQPS_CERT").expect("AMQPS_CERT wasn't set");
-    OwnedTLSConfig {
+fn env_var(name: &str) -> Result<String> {
+    env::var(name).map_err(|_| ExplorerError::other(format!("{} wasn't set", name)))
+}
+
+fn decode_base64(name: &str) -> Result<Vec<u8>> {
+    BASE64_STANDARD
+        .decode(env_var(name)?)
+        .map_err(|e| ExplorerError::other(format!("{} isn't base64: {}", name, e)))
+}
+
+async fn get_tls_config() -> Result<OwnedTLSConfig> {
+    let client_cert_and_key = decode_base64("AMQPS_CERT_CLIENT")?;
+    let cert_chain = String::from_utf8(decode_base64("AMQPS_CERT")?)
+        .map_err(|e| ExplorerError::other(format!("AMQPS_CERT isn't utf-8: {}", e)))?;
+    Ok(OwnedTLSConfig {
         identity: Some(OwnedIdentity {
-            der: BASE64_STANDARD.decode(client_cert_and_key).unwrap(),
-            password: env::var("AMQPS_CERT_PASS").expect("AMQPS_CERT_PASS wasn't set"),
+            der: client_cert_and_key,
+            password: env_var("AMQPS_CERT_PASS")?,
         }),
-        cert_chain: Some(String::from_utf8(BASE64_STANDARD.decode(cert_chain).unwrap()).unwrap()),
-    }
+        cert_chain: Some(cert_chain),
+    })
 }
 
 /// Function to create ampq channel
 pub async fn create_channel() -> Result<Channel> {
-    let addr: String = env::var("AMQPS_STRING").expect("AMQPS_STRING wasn't set");
+    let addr: String = env_var("AMQPS_STRING")?;
     let conn = Connection::connect_with_config(
         &addr,
         ConnectionProperties::default(),
-        get_tls_config().await,
+        get_tls_config().await?,
     )
     .await?;
-    conn.create_channel().await
+    Ok(conn.create_channel().await?)
+}
+
+/// Keep trying to open a channel until the broker can be reached,
+/// only gives up on errors that won't go away like a missing configuration
+pub async fn connect_channel(health: &Health) -> Result<Channel> {
+    let mut backoff = Backoff::new();
+    loop {
+        match create_channel().await {
+            Ok(channel) => {
+                health.recover("rabbitmq");
+                return Ok(channel);
+            }
+            Err(e) if !e.retryable() => return Err(e),
+            Err(e) => {
+                health.degrade("rabbitmq", &e);
+                log::warn!(
+                    "couldn't connect to rabbitmq, retrying in {:?}",
+                    backoff.next()
+                );
+                backoff.wait().await;
+            }
+        }
+    }
 }
 
 pub async fn declare_que_worker(channel: &Channel, name: &str) -> Result<()> {
@@ -56,6 +93,26 @@ pub async fn declare_que(channel: &Channel, name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Declare a queue that holds messages for `delay` before moving them to `target`
+pub async fn declare_retry_que(
+    channel: &Channel,
+    name: &str,
+    target: &str,
+    delay: Duration,
+) -> Result<()> {
+    let mut args = FieldTable::default();
+    args.insert("x-message-ttl".into(), (delay.as_millis() as u32).into());
+    args.insert("x-dead-letter-exchange".into(), LongString::from("").into());
+    args.insert(
+        "x-dead-letter-routing-key".into(),
+        LongString::from(target).into(),
+    );
+    channel
+        .queue_declare(name, QueueDeclareOptions::default(), args)
+        .await?;
+    Ok(())
+}
+
 pub async fn set_qos(channel: &Channel) -> Result<()> {
     channel
         .basic_qos(1, lapin::options::BasicQosOptions::default())
@@ -69,15 +126,30 @@ pub async fn delete_que(channel: &Channel, name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Amount of messages waiting in an existing queue
+pub async fn que_depth(channel: &Channel, name: &str) -> Result<u32> {
+    let queue = channel
+        .queue_declare(
+            name,
+            QueueDeclareOptions {
+                passive: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    Ok(queue.message_count())
+}
+
 pub async fn new_consumer(channel: &Channel, name: &str) -> Result<Consumer> {
-    channel
+    Ok(channel
         .basic_consume(
             name,
             "",
             BasicConsumeOptions::default(),
             FieldTable::default(),
         )
-        .await
+        .await?)
 }
 
 pub async fn publish(channel: &Channel, que: &str, value: String) -> Result<()> {
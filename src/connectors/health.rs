@@ -0,0 +1,165 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+use warp::{http::StatusCode, Filter};
+
+/// Minutes without progress before the healthcheck fails
+const MAX_IDLE_MINUTES: i64 = 10;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// State reported on the healthcheck, shared by the connections and the crawl loop.
+///
+/// A connection that is down degrades the health instead of stopping the process,
+/// it recovers once the connection is back.
+#[derive(Clone)]
+pub struct Health {
+    /// Minutes since epoch of the last progress
+    last_update: Arc<AtomicI64>,
+    /// Connections that are down, with their last error
+    degraded: Arc<Mutex<BTreeMap<&'static str, String>>>,
+    /// Kingston sessions by account
+    sessions: Arc<Mutex<BTreeMap<String, SessionStats>>>,
+}
+
+/// Kingston session of an account
+#[derive(Clone, Default)]
+pub struct SessionStats {
+    /// Seconds since epoch of the last authentication
+    pub authenticated_at: i64,
+    /// Times the session expired and was renewed
+    pub reauths: u32,
+    /// Seconds since epoch until which the account is left alone after failing to authenticate
+    pub quarantined_until: Option<i64>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            last_update: Arc::new(AtomicI64::new(Utc::now().timestamp() / 60)),
+            degraded: Arc::new(Mutex::new(BTreeMap::new())),
+            sessions: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Progress was made
+    pub fn touch(&self) {
+        self.last_update
+            .store(Utc::now().timestamp() / 60, Ordering::Relaxed);
+    }
+
+    pub fn degrade(&self, component: &'static str, error: impl Display) {
+        let error = format!("{:#}", error);
+        let mut degraded = self.degraded.lock().unwrap();
+        if !degraded.contains_key(component) {
+            log::warn!("{} is down: {}", component, error);
+        }
+        degraded.insert(component, error);
+    }
+
+    pub fn recover(&self, component: &'static str) {
+        if self.degraded.lock().unwrap().remove(component).is_some() {
+            log::info!("{} is back", component);
+        }
+    }
+
+    pub fn session(&self, account: &str, stats: SessionStats) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(account.to_string(), stats);
+    }
+
+    /// Minutes since the last progress, followed by the connections that are down
+    /// and the age of the Kingston sessions. Only being idle fails the check,
+    /// restarting doesn't bring a connection back
+    fn report(&self) -> (String, StatusCode) {
+        let idle = Utc::now().timestamp() / 60 - self.last_update.load(Ordering::Relaxed);
+        let degraded = self.degraded.lock().unwrap();
+
+        let mut body = format!("{}", idle);
+        for (component, error) in degraded.iter() {
+            body.push_str(&format!("\n{}: {}", component, error));
+        }
+        let now = Utc::now().timestamp();
+        for (account, stats) in self.sessions.lock().unwrap().iter() {
+            body.push_str(&format!(
+                "\nsession {}: {}m old, {} re-auths",
+                account,
+                (now - stats.authenticated_at) / 60,
+                stats.reauths
+            ));
+            if let Some(until) = stats.quarantined_until.filter(|until| *until > now) {
+                body.push_str(&format!(", quarantined for {}m", (until - now) / 60 + 1));
+            }
+        }
+
+        // error if 10 minutes without traffic
+        match idle > MAX_IDLE_MINUTES {
+            true => (body, StatusCode::SERVICE_UNAVAILABLE),
+            false => (body, StatusCode::OK),
+        }
+    }
+
+    /// Healthcheck endpoint, answers on any path
+    pub fn filter(
+        &self,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = std::convert::Infallible> + Clone {
+        let health = self.clone();
+        warp::any().map(move || {
+            let (body, status) = health.report();
+            warp::reply::with_status(body, status)
+        })
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Doubling wait between reconnect attempts
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self { next: MIN_BACKOFF }
+    }
+
+    pub async fn wait(&mut self) {
+        tokio::time::sleep(self.advance()).await;
+    }
+
+    /// Wait before the next attempt, doubles the one after it
+    pub fn advance(&mut self) -> Duration {
+        let next = self.next;
+        self.next = (self.next * 2).min(MAX_BACKOFF);
+        next
+    }
+
+    pub fn next(&self) -> Duration {
+        self.next
+    }
+
+    /// Connected again, start over with a short wait
+    pub fn reset(&mut self) {
+        self.next = MIN_BACKOFF;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
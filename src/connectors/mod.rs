@@ -1,3 +1,5 @@
 pub mod ampq;
+pub mod health;
 pub mod mongo;
 pub mod postgres;
+pub mod transport;
@@ -2,10 +2,13 @@ use std::env;
 
 use bf_sparta::cookie::Cookie;
 use dotenvy::dotenv;
-use mongodb::error::Result;
 use mongodb::{results::UpdateResult, Client, Collection};
 
 use super::models::BackendCookie;
+use crate::{
+    connectors::health::{Backoff, Health},
+    error::{ExplorerError, Result},
+};
 
 #[derive(Clone)]
 pub struct MongoClient {
@@ -14,13 +17,27 @@ pub struct MongoClient {
 }
 
 impl MongoClient {
-    pub async fn connect() -> Result<Self> {
+    /// Waits until the server answers, the driver reconnects by itself after that
+    pub async fn connect(health: &Health) -> Result<Self> {
         // Possible env
         dotenv().ok();
-        let mongo_url = env::var("MONGO_DETAILS_STRING").expect("MONGO_DETAILS_STRING must be set");
+        let mongo_url = env::var("MONGO_DETAILS_STRING")
+            .map_err(|_| ExplorerError::other("MONGO_DETAILS_STRING must be set"))?;
         // Try connect to mongo client
         let client = Client::with_uri_str(mongo_url).await?;
 
+        let mut backoff = Backoff::new();
+        while let Err(e) = client
+            .database("admin")
+            .run_command(bson::doc! {"ping": 1})
+            .await
+        {
+            health.degrade("mongo", &e);
+            log::warn!("couldn't reach mongo, retrying in {:?}", backoff.next());
+            backoff.wait().await;
+        }
+        health.recover("mongo");
+
         // Server manager DB
         let db = client.database("serverManager");
 
@@ -30,11 +47,16 @@ impl MongoClient {
         })
     }
 
+    /// Write refreshed cookies back for the other services sharing them.
+    /// Only replaces the document when it's still the one read as `previous`, so newer
+    /// cookies are never overwritten, check `matched_count` to see if it was
     pub async fn push_new_cookies(
         &mut self,
         acc_email: &str,
         cookie: &Cookie,
         ea_access_token: String,
+        refreshed_by: &str,
+        previous: &BackendCookie,
     ) -> Result<UpdateResult> {
         let id = acc_email.split('@').collect::<Vec<&str>>()[0];
         let cookie = BackendCookie {
@@ -42,18 +64,33 @@ impl MongoClient {
             sid: cookie.sid.clone(),
             remid: cookie.remid.clone(),
             ea_access_token: Some(ea_access_token.clone()),
+            updated_at: Some(bson::DateTime::now()),
+            updated_by: Some(refreshed_by.to_string()),
         };
-        self.backend_cookies
-            .replace_one(bson::doc! {"_id": format!("main-{}", id)}, cookie)
-            .upsert(true)
-            .await
+        // other services don't set updated_at, the sid tells if they replaced it.
+        // null also matches documents without the field
+        Ok(self
+            .backend_cookies
+            .replace_one(
+                bson::doc! {
+                    "_id": &previous._id,
+                    "sid": &previous.sid,
+                    "updated_at": previous.updated_at,
+                },
+                cookie,
+            )
+            .await?)
     }
 
-    pub async fn get_cookies(&mut self, acc_email: &str) -> anyhow::Result<(Cookie, String)> {
-        let backend_cookie = match self.backend_cookies.find_one(bson::doc! {"_id": format!("main-{}", acc_email.split('@').collect::<Vec<&str>>()[0])}).await? {
-            Some(result) => result,
-            None => anyhow::bail!("no cookie"),
-        };
+    pub async fn get_backend_cookie(&mut self, acc_email: &str) -> Result<BackendCookie> {
+        match self.backend_cookies.find_one(bson::doc! {"_id": format!("main-{}", acc_email.split('@').collect::<Vec<&str>>()[0])}).await? {
+            Some(result) => Ok(result),
+            None => Err(ExplorerError::Mongo(format!("no cookie for {}", acc_email))),
+        }
+    }
+
+    pub async fn get_cookies(&mut self, acc_email: &str) -> Result<(Cookie, String)> {
+        let backend_cookie = self.get_backend_cookie(acc_email).await?;
         Ok((
             backend_cookie.clone().into(),
             backend_cookie.ea_access_token.unwrap_or_default(),
@@ -7,6 +7,12 @@ pub struct BackendCookie {
     pub sid: String,
     pub remid: String,
     pub ea_access_token: Option<String>,
+    /// When the crawler last wrote refreshed cookies, missing for cookies from other services
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<bson::DateTime>,
+    /// Worker that refreshed them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_by: Option<String>,
 }
 
 impl From<BackendCookie> for Cookie {
@@ -0,0 +1,355 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::models::ExperienceVersion;
+
+/// A field that went from one value to another
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: PartialEq> Change<T> {
+    fn between(from: T, to: T) -> Option<Self> {
+        (from != to).then_some(Change { from, to })
+    }
+}
+
+/// A map of the rotation with the settings it's played with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RotationEntry {
+    pub map: Option<String>,
+    pub mode: Option<String>,
+    pub game_size: Option<i32>,
+}
+
+/// A map that stayed in the rotation but is played differently
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RotationChange {
+    pub map: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Change<Option<String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_size: Option<Change<Option<i32>>>,
+}
+
+/// A value in the JSON that was added, removed or replaced,
+/// `path` looks like `$.originalPlayground.mapRotation.maps[2].mapname`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonChange {
+    pub path: String,
+    /// None when it was added
+    pub from: Option<Value>,
+    /// None when it was removed
+    pub to: Option<Value>,
+}
+
+/// What changed between two states of an experience
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExperienceDiff {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<Change<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<Change<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maps_added: Vec<RotationEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maps_removed: Vec<RotationEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rotation_changes: Vec<RotationChange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags_added: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags_removed: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub playground_data: Vec<JsonChange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub progression_mode: Vec<JsonChange>,
+}
+
+/// The fields that get compared, borrowed from a version
+struct Compared<'a> {
+    name: &'a str,
+    description: &'a str,
+    maps: &'a [Option<String>],
+    modes: &'a [Option<String>],
+    game_sizes: &'a [Option<i32>],
+    tags: &'a Value,
+    playground_data: &'a Value,
+    progression_mode: &'a Value,
+}
+
+impl<'a> From<&'a ExperienceVersion> for Compared<'a> {
+    fn from(version: &'a ExperienceVersion) -> Self {
+        Compared {
+            name: &version.playground_name,
+            description: &version.playground_description,
+            maps: &version.maps,
+            modes: &version.modes,
+            game_sizes: &version.game_sizes,
+            tags: &version.tags,
+            playground_data: &version.playground_data,
+            progression_mode: &version.progression_mode,
+        }
+    }
+}
+
+impl Compared<'_> {
+    fn rotation(&self) -> Vec<RotationEntry> {
+        self.maps
+            .iter()
+            .enumerate()
+            .map(|(i, map)| RotationEntry {
+                map: map.clone(),
+                mode: self.modes.get(i).cloned().flatten(),
+                game_size: self.game_sizes.get(i).copied().flatten(),
+            })
+            .collect()
+    }
+}
+
+impl ExperienceDiff {
+    pub fn versions(from: &ExperienceVersion, to: &ExperienceVersion) -> Self {
+        Self::between(from.into(), to.into())
+    }
+
+    fn between(from: Compared, to: Compared) -> Self {
+        let mut diff = ExperienceDiff {
+            name: Change::between(from.name.to_string(), to.name.to_string()),
+            description: Change::between(from.description.to_string(), to.description.to_string()),
+            ..Default::default()
+        };
+        diff.diff_rotation(from.rotation(), to.rotation());
+        (diff.tags_added, diff.tags_removed) = diff_tags(from.tags, to.tags);
+        diff_json(
+            "$",
+            from.playground_data,
+            to.playground_data,
+            &mut diff.playground_data,
+        );
+        diff_json(
+            "$",
+            from.progression_mode,
+            to.progression_mode,
+            &mut diff.progression_mode,
+        );
+        diff
+    }
+
+    /// Maps are matched by name, in the order they're played when a map is in the rotation more than once
+    fn diff_rotation(&mut self, from: Vec<RotationEntry>, mut to: Vec<RotationEntry>) {
+        for old in from {
+            match to.iter().position(|new| new.map == old.map) {
+                Some(i) => {
+                    let new = to.remove(i);
+                    let change = RotationChange {
+                        map: old.map,
+                        mode: Change::between(old.mode, new.mode),
+                        game_size: Change::between(old.game_size, new.game_size),
+                    };
+                    if change.mode.is_some() || change.game_size.is_some() {
+                        self.rotation_changes.push(change);
+                    }
+                }
+                None => self.maps_removed.push(old),
+            }
+        }
+        self.maps_added = to;
+    }
+}
+
+/// Tags are compared as a set, returns the added and removed ones
+fn diff_tags(from: &Value, to: &Value) -> (Vec<Value>, Vec<Value>) {
+    let as_list = |tags: &Value| match tags {
+        Value::Array(tags) => tags.clone(),
+        Value::Null => vec![],
+        tag => vec![tag.clone()],
+    };
+    let (from, to) = (as_list(from), as_list(to));
+    let added = to
+        .iter()
+        .filter(|tag| !from.contains(tag))
+        .cloned()
+        .collect();
+    let removed = from
+        .iter()
+        .filter(|tag| !to.contains(tag))
+        .cloned()
+        .collect();
+    (added, removed)
+}
+
+/// Every value under `path` that differs, objects are compared by key and arrays by index
+fn diff_json(path: &str, from: &Value, to: &Value, changes: &mut Vec<JsonChange>) {
+    match (from, to) {
+        (Value::Object(from), Value::Object(to)) => {
+            for (key, old) in from {
+                let path = format!("{}.{}", path, key);
+                match to.get(key) {
+                    Some(new) => diff_json(&path, old, new, changes),
+                    None => changes.push(JsonChange {
+                        path,
+                        from: Some(old.clone()),
+                        to: None,
+                    }),
+                }
+            }
+            for (key, new) in to {
+                if !from.contains_key(key) {
+                    changes.push(JsonChange {
+                        path: format!("{}.{}", path, key),
+                        from: None,
+                        to: Some(new.clone()),
+                    });
+                }
+            }
+        }
+        (Value::Array(from), Value::Array(to)) => {
+            for i in 0..from.len().max(to.len()) {
+                let path = format!("{}[{}]", path, i);
+                match (from.get(i), to.get(i)) {
+                    (Some(old), Some(new)) => diff_json(&path, old, new, changes),
+                    (old, new) => changes.push(JsonChange {
+                        path,
+                        from: old.cloned(),
+                        to: new.cloned(),
+                    }),
+                }
+            }
+        }
+        (from, to) if from != to => changes.push(JsonChange {
+            path: path.to_string(),
+            from: Some(from.clone()),
+            to: Some(to.clone()),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn entry(map: &str, mode: &str, game_size: i32) -> RotationEntry {
+        RotationEntry {
+            map: Some(map.to_string()),
+            mode: Some(mode.to_string()),
+            game_size: Some(game_size),
+        }
+    }
+
+    fn diff_rotation(from: Vec<RotationEntry>, to: Vec<RotationEntry>) -> ExperienceDiff {
+        let mut diff = ExperienceDiff::default();
+        diff.diff_rotation(from, to);
+        diff
+    }
+
+    fn diff_json(from: Value, to: Value) -> Vec<JsonChange> {
+        let mut changes = vec![];
+        super::diff_json("$", &from, &to, &mut changes);
+        changes
+    }
+
+    fn change(path: &str, from: Option<Value>, to: Option<Value>) -> JsonChange {
+        JsonChange {
+            path: path.to_string(),
+            from,
+            to,
+        }
+    }
+
+    #[test]
+    fn repeated_maps_are_matched_in_order() {
+        let diff = diff_rotation(
+            vec![
+                entry("kaleidoscope", "conquest", 64),
+                entry("hourglass", "conquest", 64),
+                entry("kaleidoscope", "breakthrough", 64),
+            ],
+            vec![
+                entry("kaleidoscope", "conquest", 64),
+                entry("kaleidoscope", "rush", 32),
+                entry("orbital", "conquest", 64),
+            ],
+        );
+        assert_eq!(
+            diff.rotation_changes,
+            [RotationChange {
+                map: Some("kaleidoscope".to_string()),
+                mode: Change::between(Some("breakthrough".to_string()), Some("rush".to_string())),
+                game_size: Change::between(Some(64), Some(32)),
+            }]
+        );
+        assert_eq!(diff.maps_removed, [entry("hourglass", "conquest", 64)]);
+        assert_eq!(diff.maps_added, [entry("orbital", "conquest", 64)]);
+    }
+
+    #[test]
+    fn repeated_map_dropped_once() {
+        let diff = diff_rotation(
+            vec![
+                entry("kaleidoscope", "conquest", 64),
+                entry("kaleidoscope", "conquest", 64),
+            ],
+            vec![entry("kaleidoscope", "conquest", 64)],
+        );
+        assert!(diff.rotation_changes.is_empty());
+        assert!(diff.maps_added.is_empty());
+        assert_eq!(diff.maps_removed, [entry("kaleidoscope", "conquest", 64)]);
+    }
+
+    #[test]
+    fn arrays_are_compared_by_index() {
+        let grown = diff_json(json!({"maps": [1, 2]}), json!({"maps": [1, 3, 4]}));
+        assert_eq!(
+            grown,
+            [
+                change("$.maps[1]", Some(json!(2)), Some(json!(3))),
+                change("$.maps[2]", None, Some(json!(4))),
+            ]
+        );
+
+        let shrunk = diff_json(json!({"maps": [1, 3, 4]}), json!({"maps": [1]}));
+        assert_eq!(
+            shrunk,
+            [
+                change("$.maps[1]", Some(json!(3)), None),
+                change("$.maps[2]", Some(json!(4)), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn null_is_not_a_missing_key() {
+        assert_eq!(
+            diff_json(json!({"secret": null}), json!({})),
+            [change("$.secret", Some(Value::Null), None)]
+        );
+        assert_eq!(
+            diff_json(json!({}), json!({"secret": null})),
+            [change("$.secret", None, Some(Value::Null))]
+        );
+        assert_eq!(
+            diff_json(json!({"secret": null}), json!({"secret": "1234"})),
+            [change("$.secret", Some(Value::Null), Some(json!("1234")))]
+        );
+        assert!(diff_json(json!({"secret": null}), json!({"secret": null})).is_empty());
+    }
+
+    #[test]
+    fn tags_are_compared_as_a_set() {
+        let (added, removed) =
+            diff_tags(&json!(["casual", "hardcore"]), &json!(["hardcore", "xp"]));
+        assert_eq!(added, [json!("xp")]);
+        assert_eq!(removed, [json!("casual")]);
+
+        let (added, removed) = diff_tags(&json!(["casual", "xp"]), &json!(["xp", "casual"]));
+        assert!(added.is_empty() && removed.is_empty());
+
+        let (added, removed) = diff_tags(&Value::Null, &json!(["xp"]));
+        assert_eq!((added, removed), (vec![json!("xp")], vec![]));
+    }
+}
@@ -1,29 +1,93 @@
-use std::env;
+use std::{env, time::Duration};
 
+use crate::connectors::postgres::schema::crawl_attempts;
 use crate::connectors::postgres::schema::current_experiences::dsl::*;
+use crate::connectors::postgres::schema::dead_letters;
 use crate::connectors::postgres::schema::experiences::dsl::*;
-use diesel::{associations::HasTable, prelude::*, upsert::excluded};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{
+    associations::HasTable,
+    dsl::sql,
+    prelude::*,
+    r2d2::{ConnectionManager, Pool, PooledConnection},
+    sql_types::Double,
+    upsert::excluded,
+};
 use dotenvy::dotenv;
 
-use super::models::{CurrentExperience, Experience};
+use super::models::{CrawlAttempt, CrawlOutcome, CurrentExperience, DeadLetter, Experience};
+use super::rotations::sync_rotation;
+use super::tags::sync_tags;
+use super::versions::store_version;
+use crate::connectors::health::Health;
+use crate::error::{ExplorerError, Result};
 
+const DEFAULT_POOL_SIZE: u32 = 2;
+/// How long a query waits for a connection before it fails
+const CHECKOUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type PgPooledConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+/// Connections are checked before use and re-established when they broke
+#[derive(Clone)]
 pub struct PostgresClient {
-    pub client: PgConnection,
+    pool: Pool<ConnectionManager<PgConnection>>,
+    health: Health,
 }
 
 impl PostgresClient {
-    pub fn connect() -> anyhow::Result<Self> {
+    /// Doesn't wait for the database, queries fail and degrade `health` until it can be reached
+    pub fn connect(health: &Health) -> Result<Self> {
         dotenv().ok();
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let connection = PgConnection::establish(&database_url).unwrap();
+        let database_url = env::var("DATABASE_URL")
+            .map_err(|_| ExplorerError::other("DATABASE_URL must be set"))?;
+        let pool_size = env::var("POSTGRES_POOL_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_POOL_SIZE);
+        let pool = Pool::builder()
+            .max_size(pool_size)
+            .min_idle(Some(0))
+            .connection_timeout(CHECKOUT_TIMEOUT)
+            .build_unchecked(ConnectionManager::new(database_url));
+
+        Ok(PostgresClient {
+            pool,
+            health: health.clone(),
+        })
+    }
+
+    /// Client whose queries fail right away, for tests that don't need a database
+    #[cfg(test)]
+    pub fn unreachable(health: &Health) -> Self {
+        let pool = Pool::builder()
+            .min_idle(Some(0))
+            .connection_timeout(Duration::from_millis(10))
+            .build_unchecked(ConnectionManager::new("postgres://127.0.0.1:1/explorer"));
+        PostgresClient {
+            pool,
+            health: health.clone(),
+        }
+    }
 
-        Ok(PostgresClient { client: connection })
+    /// Working connection from the pool
+    pub fn conn(&self) -> Result<PgPooledConnection> {
+        match self.pool.get() {
+            Ok(conn) => {
+                self.health.recover("postgres");
+                Ok(conn)
+            }
+            Err(e) => {
+                self.health.degrade("postgres", &e);
+                Err(e.into())
+            }
+        }
     }
 
-    pub fn current_experience(&mut self) -> anyhow::Result<i32> {
+    pub fn current_experience(&mut self) -> Result<i32> {
         let current_id: Option<i32> = current_experiences::table()
             .select(code)
-            .first(&mut self.client)
+            .first(&mut self.conn()?)
             .optional()?;
 
         if let Some(e) = current_id {
@@ -31,11 +95,11 @@ impl PostgresClient {
         }
         let _ = diesel::insert_into(current_experiences::table())
             .values(CurrentExperience { id: 1, code: 1 })
-            .execute(&mut self.client);
+            .execute(&mut self.conn()?);
         return Ok(1);
     }
 
-    pub fn set_current_experience(&mut self, current_id: i32) {
+    pub fn set_current_experience(&mut self, current_id: i32) -> Result<()> {
         let value = &CurrentExperience {
             id: 1,
             code: current_id,
@@ -45,15 +109,19 @@ impl PostgresClient {
             .on_conflict(id)
             .do_update()
             .set(value)
-            .execute(&mut self.client)
-            .expect("Error saving current experience id");
+            .execute(&mut self.conn()?)?;
+        Ok(())
     }
 
     pub fn has_experience(&mut self, _share_code: String) -> bool {
+        let mut conn = match self.conn() {
+            Ok(conn) => conn,
+            Err(_) => return false,
+        };
         let experience = experiences::table()
             .filter(share_code.eq(_share_code))
             .select(Experience::as_select())
-            .first(&mut self.client)
+            .first(&mut conn)
             .optional();
 
         match experience {
@@ -63,45 +131,181 @@ impl PostgresClient {
         }
     }
 
-    pub fn add_or_update_experience(&mut self, experience: Experience) {
-        let _ = diesel::insert_into(experiences::table())
-            .values(&experience)
-            .on_conflict(experience_id)
-            .do_update()
-            .set((
-                experience_id.eq(&experience.experience_id),
-                share_code.eq(&experience.share_code),
-                playground_name.eq(&experience.playground_name),
-                playground_description.eq(&experience.playground_description),
-                playground_created_at.eq(&experience.playground_created_at),
-                playground_updated_at.eq(&experience.playground_updated_at),
-                playground_data.eq(&experience.playground_data),
-                tags.eq(&experience.tags),
-                progression_mode.eq(&experience.progression_mode),
-                updated_at.eq(&experience.updated_at),
-                created_at.eq(excluded(created_at)),
-            ))
-            .execute(&mut self.client)
-            .expect("Error saving new post");
+    /// Store the experience with its rotation and tags, and a snapshot in its history when the playground changed.
+    /// A tombstoned experience that is seen again loses its `deleted_at`
+    pub fn add_or_update_experience(&mut self, experience: Experience) -> Result<()> {
+        self.conn()?.transaction::<_, ExplorerError, _>(|conn| {
+            if let Some(version) = store_version(conn, &experience)? {
+                log::debug!(
+                    "stored version {} of experience {}",
+                    version,
+                    experience.experience_id
+                );
+            }
+            diesel::insert_into(experiences::table())
+                .values(&experience)
+                .on_conflict(experience_id)
+                .do_update()
+                .set((
+                    experience_id.eq(&experience.experience_id),
+                    share_code.eq(&experience.share_code),
+                    playground_name.eq(&experience.playground_name),
+                    playground_description.eq(&experience.playground_description),
+                    playground_created_at.eq(&experience.playground_created_at),
+                    playground_updated_at.eq(&experience.playground_updated_at),
+                    playground_data.eq(&experience.playground_data),
+                    tags.eq(&experience.tags),
+                    progression_mode.eq(&experience.progression_mode),
+                    maps.eq(&experience.maps),
+                    game_sizes.eq(&experience.game_sizes),
+                    modes.eq(&experience.modes),
+                    updated_at.eq(&experience.updated_at),
+                    last_seen_at.eq(&experience.last_seen_at),
+                    deleted_at.eq(None::<NaiveDateTime>),
+                    created_at.eq(excluded(created_at)),
+                ))
+                .execute(conn)?;
+            sync_rotation(conn, &experience)?;
+            sync_tags(conn, &experience)
+        })
     }
 
-    pub fn update_experience(&mut self, experience: Experience) {
-        let _ = diesel::update(experiences::table())
+    pub fn update_experience(&mut self, experience: Experience) -> Result<()> {
+        diesel::update(experiences::table())
             .set(experience)
-            .execute(&mut self.client)
-            .expect("Error updating post");
+            .execute(&mut self.conn()?)?;
+        Ok(())
     }
 
-    pub fn get_last_experience(&mut self) -> anyhow::Result<i32> {
+    /// When the experience was last returned by EA, None if it isn't stored
+    pub fn last_seen(&mut self, _experience_id: i32) -> Result<Option<NaiveDateTime>> {
+        Ok(experiences::table()
+            .select(last_seen_at)
+            .filter(experience_id.eq(_experience_id))
+            .first(&mut self.conn()?)
+            .optional()?)
+    }
+
+    /// Tombstone an experience that no longer returns a playground,
+    /// returns false if it isn't stored or already marked
+    pub fn mark_experience_deleted(&mut self, _experience_id: i32) -> Result<bool> {
+        let marked = diesel::update(experiences::table())
+            .filter(experience_id.eq(_experience_id))
+            .filter(deleted_at.is_null())
+            .set(deleted_at.eq(Utc::now().naive_utc()))
+            .execute(&mut self.conn()?)?;
+        Ok(marked > 0)
+    }
+
+    pub fn get_last_experience(&mut self) -> Result<i32> {
         let experience: Option<i32> = experiences::table()
             .select(experience_id)
             .order_by(experience_id.desc())
-            .first(&mut self.client)
+            .first(&mut self.conn()?)
             .optional()?;
 
         if let Some(e) = experience {
             return Ok(e);
         }
-        anyhow::bail!("Database is empty!")
+        Err(ExplorerError::Database("Database is empty!".to_string()))
+    }
+
+    /// Experiences not updated since `older_than`, the longest unchanged first,
+    /// with a higher priority for the ones their creator edited recently
+    pub fn stale_experiences(&mut self, older_than: NaiveDateTime, limit: i64) -> Result<Vec<i32>> {
+        Ok(experiences::table()
+            .select(experience_id)
+            .filter(updated_at.lt(older_than))
+            .filter(deleted_at.is_null())
+            .order(
+                sql::<Double>(
+                    "(EXTRACT(EPOCH FROM (NOW() AT TIME ZONE 'UTC' - updated_at)) \
+                    / (EXTRACT(EPOCH FROM (NOW() AT TIME ZONE 'UTC' - playground_updated_at)) + 86400))::float8",
+                )
+                .desc(),
+            )
+            .limit(limit)
+            .load(&mut self.conn()?)?)
+    }
+
+    /// Mark a code as handed out to a worker, doesn't count as an attempt
+    pub fn mark_dispatched(&mut self, _experience_id: i32) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        diesel::insert_into(crawl_attempts::table)
+            .values(&CrawlAttempt {
+                experience_id: _experience_id,
+                outcome: CrawlOutcome::Pending.as_str().to_string(),
+                attempt_count: 0,
+                last_error: None,
+                first_attempt_at: now,
+                last_attempt_at: now,
+            })
+            .on_conflict(crawl_attempts::experience_id)
+            .do_update()
+            .set((
+                crawl_attempts::outcome.eq(excluded(crawl_attempts::outcome)),
+                crawl_attempts::last_attempt_at.eq(excluded(crawl_attempts::last_attempt_at)),
+            ))
+            .execute(&mut self.conn()?)?;
+        Ok(())
+    }
+
+    /// Store the result of fetching a code in the crawl ledger
+    pub fn record_attempt(
+        &mut self,
+        _experience_id: i32,
+        outcome: CrawlOutcome,
+        error: Option<String>,
+    ) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        diesel::insert_into(crawl_attempts::table)
+            .values(&CrawlAttempt {
+                experience_id: _experience_id,
+                outcome: outcome.as_str().to_string(),
+                attempt_count: 1,
+                last_error: error,
+                first_attempt_at: now,
+                last_attempt_at: now,
+            })
+            .on_conflict(crawl_attempts::experience_id)
+            .do_update()
+            .set((
+                crawl_attempts::outcome.eq(excluded(crawl_attempts::outcome)),
+                crawl_attempts::attempt_count.eq(crawl_attempts::attempt_count + 1),
+                crawl_attempts::last_error.eq(excluded(crawl_attempts::last_error)),
+                crawl_attempts::last_attempt_at.eq(excluded(crawl_attempts::last_attempt_at)),
+            ))
+            .execute(&mut self.conn()?)?;
+        Ok(())
+    }
+
+    pub fn get_crawl_attempt(&mut self, _experience_id: i32) -> Result<Option<CrawlAttempt>> {
+        Ok(crawl_attempts::table
+            .find(_experience_id)
+            .select(CrawlAttempt::as_select())
+            .first(&mut self.conn()?)
+            .optional()?)
+    }
+
+    /// Give up on a code after it ran out of retries
+    pub fn add_dead_letter(
+        &mut self,
+        _experience_id: i32,
+        attempts: i32,
+        error: Option<String>,
+    ) -> Result<()> {
+        let value = &DeadLetter {
+            experience_id: _experience_id,
+            attempts,
+            last_error: error,
+            created_at: Utc::now().naive_utc(),
+        };
+        diesel::insert_into(dead_letters::table)
+            .values(value)
+            .on_conflict(dead_letters::experience_id)
+            .do_update()
+            .set(value)
+            .execute(&mut self.conn()?)?;
+        Ok(())
     }
 }
@@ -1,3 +1,9 @@
+pub mod diff;
 pub mod lib;
 pub mod models;
+pub mod queue;
+pub mod rate_limit;
+pub mod rotations;
 pub mod schema;
+pub mod tags;
+pub mod versions;
@@ -1,11 +1,18 @@
 use crate::{
-    connectors::{self, postgres::schema::current_experiences},
+    connectors::{
+        self,
+        postgres::schema::{
+            crawl_attempts, crawl_leases, current_experiences, dead_letters, dispatched_work,
+            experience_rotations, experience_tags, experience_versions, rate_limits, tags,
+        },
+    },
     experience_code::ExperienceCode,
 };
 use chrono::{NaiveDateTime, Utc};
 use connectors::postgres::schema::experiences;
 use diesel::prelude::*;
 use grpc_rust::modules::communitygames::PlaygroundInfo;
+use serde::{Deserialize, Serialize};
 
 #[derive(AsChangeset, Queryable, Selectable, Insertable)]
 #[diesel(table_name = experiences)]
@@ -25,6 +32,10 @@ pub struct Experience {
     pub progression_mode: serde_json::Value,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// Last time the experience was returned by EA
+    pub last_seen_at: NaiveDateTime,
+    /// Set when the experience no longer returns a playground
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
 impl Experience {
@@ -77,54 +88,249 @@ impl Experience {
             modes: modes,
             created_at: Utc::now().naive_utc(),
             updated_at: Utc::now().naive_utc(),
+            last_seen_at: Utc::now().naive_utc(),
+            deleted_at: None,
         })
     }
 
-    // pub fn init_gametools(
-    //     experience_code: ExperienceCode,
-    //     playground: serde_json::Value,
-    // ) -> anyhow::Result<Self> {
-    //     let default_value = &serde_json::Value::String("".to_string());
-    //     let p_data = playground.clone().get("originalPlayground").unwrap();
-    //     Ok(Experience {
-    //         experience_id: experience_code.to_usize()? as i32,
-    //         share_code: experience_code.into(),
-    //         playground_name: p_data
-    //             .get("playgroundName")
-    //             .unwrap_or()
-    //             .as_str()
-    //             .unwrap_or_default()
-    //             .to_string(),
-    //         playground_description: p_data.playground_description,
-    //         playground_data: serde_json::to_value(&playground)?,
-    //         tags: serde_json::to_value(playground.clone().tag)?,
-    //         progression_mode: serde_json::to_value(playground.clone().progression_mode)?,
-    //         playground_created_at: chrono::DateTime::from_timestamp(
-    //             p_data.created_at.unwrap_or_default().seconds.into(),
-    //             p_data
-    //                 .created_at
-    //                 .unwrap_or_default()
-    //                 .nanos
-    //                 .try_into()
-    //                 .unwrap(),
-    //         )
-    //         .unwrap_or_default()
-    //         .naive_utc(),
-    //         playground_updated_at: chrono::DateTime::from_timestamp(
-    //             p_data.updated_at.unwrap_or_default().seconds.into(),
-    //             p_data
-    //                 .updated_at
-    //                 .unwrap_or_default()
-    //                 .nanos
-    //                 .try_into()
-    //                 .unwrap(),
-    //         )
-    //         .unwrap_or_default()
-    //         .naive_utc(),
-    //         created_at: Utc::now().naive_utc(),
-    //         updated_at: Utc::now().naive_utc(),
-    //     })
-    // }
+    /// Map the JSON of the gametools playground endpoint, the camelCase form of `PlaygroundInfo`.
+    /// When it reads as a `PlaygroundInfo` it's stored in Kingston's shape,
+    /// so the content hash doesn't change with the source
+    pub fn init_gametools(
+        experience_code: ExperienceCode,
+        playground: serde_json::Value,
+    ) -> anyhow::Result<Self> {
+        let parsed = serde_json::from_value::<PlaygroundInfo>(playground.clone())
+            .or_else(|_| serde_json::from_value(snake_case_keys(playground.clone())));
+        match parsed {
+            Ok(playground) => return Self::init_standalone(experience_code, playground),
+            Err(e) => log::debug!("gametools playground isn't a PlaygroundInfo: {}", e),
+        }
+
+        let p_data = playground
+            .get("originalPlayground")
+            .cloned()
+            .unwrap_or_default();
+        let text = |key: &str| {
+            p_data
+                .get(key)
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let mut maps: Vec<Option<String>> = vec![];
+        let mut game_sizes: Vec<Option<i32>> = vec![];
+        let mut modes: Vec<Option<String>> = vec![];
+        let map_rotations = p_data
+            .pointer("/mapRotation/maps")
+            .and_then(|maps| maps.as_array())
+            .cloned()
+            .unwrap_or_default();
+        for map_rotation in map_rotations {
+            maps.push(map_rotation["mapname"].as_str().map(str::to_string));
+            game_sizes.push(map_rotation["gameSize"].as_i64().map(|size| size as i32));
+            modes.push(map_rotation["mode"].as_str().map(str::to_string));
+        }
+        Ok(Experience {
+            experience_id: experience_code.to_usize()? as i32,
+            share_code: experience_code.into(),
+            playground_name: text("playgroundName"),
+            playground_description: text("playgroundDescription"),
+            tags: playground.get("tag").cloned().unwrap_or_default(),
+            progression_mode: playground
+                .get("progressionMode")
+                .cloned()
+                .unwrap_or_default(),
+            playground_created_at: json_timestamp(p_data.get("createdAt")),
+            playground_updated_at: json_timestamp(p_data.get("updatedAt")),
+            playground_data: playground,
+            maps,
+            game_sizes,
+            modes,
+            created_at: Utc::now().naive_utc(),
+            updated_at: Utc::now().naive_utc(),
+            last_seen_at: Utc::now().naive_utc(),
+            deleted_at: None,
+        })
+    }
+}
+
+/// Rename the camelCase keys of a JSON object and everything in it to snake_case
+fn snake_case_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => object
+            .into_iter()
+            .map(|(key, value)| {
+                let mut snake = String::with_capacity(key.len());
+                for c in key.chars() {
+                    if c.is_ascii_uppercase() {
+                        snake.push('_');
+                    }
+                    snake.push(c.to_ascii_lowercase());
+                }
+                (snake, snake_case_keys(value))
+            })
+            .collect(),
+        serde_json::Value::Array(values) => values.into_iter().map(snake_case_keys).collect(),
+        value => value,
+    }
+}
+
+/// Timestamp as `{"seconds", "nanos"}` (seconds can be a string) or as RFC 3339
+fn json_timestamp(value: Option<&serde_json::Value>) -> NaiveDateTime {
+    let value = match value {
+        Some(value) => value,
+        None => return NaiveDateTime::default(),
+    };
+    if let Some(text) = value.as_str() {
+        return chrono::DateTime::parse_from_rfc3339(text)
+            .map(|date| date.naive_utc())
+            .unwrap_or_default();
+    }
+    let number = |key: &str| {
+        let field = &value[key];
+        field
+            .as_i64()
+            .or_else(|| field.as_str().and_then(|text| text.parse().ok()))
+            .unwrap_or_default()
+    };
+    chrono::DateTime::from_timestamp(number("seconds"), number("nanos") as u32)
+        .unwrap_or_default()
+        .naive_utc()
+}
+
+/// A map of the rotation, `position` is the index in the arrays of the experience
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = experience_rotations)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ExperienceRotation {
+    pub experience_id: i32,
+    pub position: i32,
+    pub map_name: Option<String>,
+    pub mode: Option<String>,
+    pub game_size: Option<i32>,
+}
+
+impl ExperienceRotation {
+    pub fn from_experience(experience: &Experience) -> Vec<Self> {
+        experience
+            .maps
+            .iter()
+            .enumerate()
+            .map(|(i, map)| ExperienceRotation {
+                experience_id: experience.experience_id,
+                position: i as i32,
+                map_name: map.clone(),
+                mode: experience.modes.get(i).cloned().flatten(),
+                game_size: experience.game_sizes.get(i).copied().flatten(),
+            })
+            .collect()
+    }
+}
+
+/// A tag of the catalog, `name` is the id EA gives it
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = tags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+    pub category: Option<String>,
+    /// When an experience with it was first stored
+    pub first_seen: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = tags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewTag {
+    pub name: String,
+    pub category: Option<String>,
+    pub first_seen: NaiveDateTime,
+}
+
+impl NewTag {
+    /// The tags in the JSON of an experience, Kingston and gametools name the fields differently
+    pub fn from_experience(experience: &Experience) -> Vec<Self> {
+        let mut found: Vec<NewTag> = vec![];
+        for tag in experience.tags.as_array().into_iter().flatten() {
+            let name = match tag
+                .get("tagId")
+                .or_else(|| tag.get("tag_id"))
+                .and_then(|name| name.as_str())
+            {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if found.iter().any(|tag| tag.name == name) {
+                continue;
+            }
+            let category = match tag.get("category") {
+                Some(serde_json::Value::String(category)) => Some(category.clone()),
+                Some(serde_json::Value::Null) | None => None,
+                Some(category) => Some(category.to_string()),
+            };
+            found.push(NewTag {
+                name,
+                category,
+                first_seen: experience.updated_at,
+            });
+        }
+        found
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = experience_tags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ExperienceTag {
+    pub experience_id: i32,
+    pub tag_id: i32,
+}
+
+/// Snapshot of an experience, stored whenever its playground changed
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = experience_versions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ExperienceVersion {
+    pub experience_id: i32,
+    /// Counts up from 1 for every experience
+    pub version: i32,
+    /// Hex sha256 of the playground data
+    pub content_hash: String,
+    pub playground_name: String,
+    pub playground_description: String,
+    pub playground_data: serde_json::Value,
+    pub tags: serde_json::Value,
+    pub progression_mode: serde_json::Value,
+    pub maps: Vec<Option<String>>,
+    pub game_sizes: Vec<Option<i32>>,
+    pub modes: Vec<Option<String>>,
+    pub playground_updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    /// ExperienceDiff with the version before it, None for the first version
+    pub changes: Option<serde_json::Value>,
+}
+
+impl ExperienceVersion {
+    pub fn snapshot(experience: &Experience, version: i32, content_hash: String) -> Self {
+        ExperienceVersion {
+            experience_id: experience.experience_id,
+            version,
+            content_hash,
+            playground_name: experience.playground_name.clone(),
+            playground_description: experience.playground_description.clone(),
+            playground_data: experience.playground_data.clone(),
+            tags: experience.tags.clone(),
+            progression_mode: experience.progression_mode.clone(),
+            maps: experience.maps.clone(),
+            game_sizes: experience.game_sizes.clone(),
+            modes: experience.modes.clone(),
+            playground_updated_at: experience.playground_updated_at,
+            created_at: experience.updated_at,
+            changes: None,
+        }
+    }
 }
 
 #[derive(AsChangeset, Queryable, Selectable, Insertable)]
@@ -134,3 +340,94 @@ pub struct CurrentExperience {
     pub id: i32,
     pub code: i32,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrawlOutcome {
+    /// Handed out to a worker, no answer yet
+    Pending,
+    Found,
+    /// No playground returned for the code
+    NotFound,
+    /// Kingston asked us to slow down
+    Throttled,
+    /// The session or access token was rejected
+    Unauthorized,
+    Error,
+}
+
+impl CrawlOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CrawlOutcome::Pending => "pending",
+            CrawlOutcome::Found => "found",
+            CrawlOutcome::NotFound => "not_found",
+            CrawlOutcome::Throttled => "throttled",
+            CrawlOutcome::Unauthorized => "unauthorized",
+            CrawlOutcome::Error => "error",
+        }
+    }
+}
+
+#[derive(AsChangeset, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crawl_attempts)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CrawlAttempt {
+    pub experience_id: i32,
+    pub outcome: String,
+    pub attempt_count: i32,
+    pub last_error: Option<String>,
+    pub first_attempt_at: NaiveDateTime,
+    pub last_attempt_at: NaiveDateTime,
+}
+
+#[derive(AsChangeset, Queryable, Selectable, Insertable)]
+#[diesel(table_name = dead_letters)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DeadLetter {
+    pub experience_id: i32,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Range of codes `range_start..range_end` handed out to a single worker
+#[derive(AsChangeset, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crawl_leases)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CrawlLease {
+    pub range_start: i32,
+    pub range_end: i32,
+    /// First code of the range that isn't done yet
+    pub next_code: i32,
+    pub worker: Option<String>,
+    pub leased_until: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+/// Work the host handed out and hadn't heard back about when it stopped
+#[derive(AsChangeset, Queryable, Selectable, Insertable)]
+#[diesel(table_name = dispatched_work)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DispatchedWork {
+    pub code: i32,
+    pub kind: String,
+    pub attempt: i32,
+    pub saved_at: NaiveDateTime,
+}
+
+/// Token bucket shared by every process on the database
+#[derive(AsChangeset, Queryable, Selectable, Insertable)]
+#[diesel(table_name = rate_limits)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct RateLimit {
+    pub name: String,
+    pub tokens: f64,
+    pub updated_at: NaiveDateTime,
+    /// Share of the configured rate in use, lowered when Kingston throttles
+    pub rate_factor: f64,
+    /// Throttles in a row, sets the length of the next backoff
+    pub backoff_level: i32,
+    /// Nobody sends requests until then
+    pub backoff_until: Option<NaiveDateTime>,
+}
@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use chrono::{TimeDelta, Utc};
+use diesel::{dsl::max, prelude::*};
+
+use crate::connectors::postgres::schema::{crawl_leases::dsl::*, dispatched_work};
+
+use crate::error::{ExplorerError, Result};
+
+use super::{
+    lib::PostgresClient,
+    models::{CrawlLease, DispatchedWork},
+};
+
+/// Attempts to claim a fresh range when other workers claim the same one first
+const NEW_RANGE_ATTEMPTS: usize = 5;
+
+/// Job queue without RabbitMQ, workers lease ranges of codes that expire when not renewed
+impl PostgresClient {
+    /// Take over an expired lease, or claim a new range after the last one
+    pub fn lease_range(
+        &mut self,
+        _worker: &str,
+        lease_for: Duration,
+        range_size: i32,
+    ) -> Result<CrawlLease> {
+        let cursor = self.current_experience()?;
+        let lease_for = TimeDelta::from_std(lease_for)?;
+
+        for _ in 0..NEW_RANGE_ATTEMPTS {
+            let lease = self.conn()?.transaction::<_, ExplorerError, _>(|conn| {
+                let now = Utc::now().naive_utc();
+                let expired: Option<CrawlLease> = crawl_leases
+                    .filter(completed_at.is_null())
+                    .filter(leased_until.is_null().or(leased_until.lt(now)))
+                    .order(range_start)
+                    .select(CrawlLease::as_select())
+                    .for_update()
+                    .skip_locked()
+                    .first(conn)
+                    .optional()?;
+
+                if let Some(lease) = expired {
+                    return Ok(Some(
+                        diesel::update(crawl_leases.find(lease.range_start))
+                            .set((worker.eq(_worker), leased_until.eq(now + lease_for)))
+                            .returning(CrawlLease::as_returning())
+                            .get_result(conn)?,
+                    ));
+                }
+
+                let start = crawl_leases
+                    .select(max(range_end))
+                    .first::<Option<i32>>(conn)?
+                    .unwrap_or(cursor);
+                Ok(diesel::insert_into(crawl_leases)
+                    .values(&CrawlLease {
+                        range_start: start,
+                        range_end: start + range_size,
+                        next_code: start,
+                        worker: Some(_worker.to_string()),
+                        leased_until: Some(now + lease_for),
+                        completed_at: None,
+                    })
+                    .on_conflict_do_nothing()
+                    .returning(CrawlLease::as_returning())
+                    .get_result(conn)
+                    .optional()?)
+            })?;
+
+            if let Some(lease) = lease {
+                return Ok(lease);
+            }
+        }
+        Err(ExplorerError::Database(
+            "couldn't claim a range of codes".to_string(),
+        ))
+    }
+
+    /// Save progress and extend the lease, returns false if another worker took it over
+    pub fn renew_lease(
+        &mut self,
+        _range_start: i32,
+        _worker: &str,
+        _next_code: i32,
+        lease_for: Duration,
+    ) -> Result<bool> {
+        let updated = diesel::update(crawl_leases.find(_range_start))
+            .filter(worker.eq(_worker))
+            .set((
+                next_code.eq(_next_code),
+                leased_until.eq(Utc::now().naive_utc() + TimeDelta::from_std(lease_for)?),
+            ))
+            .execute(&mut self.conn()?)?;
+        Ok(updated > 0)
+    }
+
+    /// Give up the lease so another worker can continue it right away
+    pub fn release_lease(&mut self, _range_start: i32, _worker: &str) -> Result<()> {
+        diesel::update(crawl_leases.find(_range_start))
+            .filter(worker.eq(_worker))
+            .set(leased_until.eq(None::<chrono::NaiveDateTime>))
+            .execute(&mut self.conn()?)?;
+        Ok(())
+    }
+
+    /// Mark the range as done and move the cursor to the first code that isn't
+    pub fn complete_lease(&mut self, _range_start: i32, _worker: &str) -> Result<()> {
+        diesel::update(crawl_leases.find(_range_start))
+            .filter(worker.eq(_worker))
+            .set((
+                next_code.eq(range_end),
+                completed_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(&mut self.conn()?)?;
+
+        let open: Option<i32> = crawl_leases
+            .filter(completed_at.is_null())
+            .select(diesel::dsl::min(next_code))
+            .first(&mut self.conn()?)?;
+        let cursor = match open {
+            Some(cursor) => Some(cursor),
+            None => crawl_leases
+                .select(max(range_end))
+                .first(&mut self.conn()?)?,
+        };
+        if let Some(cursor) = cursor {
+            self.set_current_experience(cursor)?;
+        }
+        Ok(())
+    }
+
+    /// Crawl again from `code` after new experiences showed up past ranges that were done,
+    /// drops the ranges after it and opens the one holding it again
+    pub fn restart_leases(&mut self, code: i32) -> Result<()> {
+        self.conn()?.transaction::<_, ExplorerError, _>(|conn| {
+            diesel::delete(crawl_leases.filter(range_start.gt(code))).execute(conn)?;
+            diesel::update(
+                crawl_leases
+                    .filter(range_start.le(code))
+                    .filter(range_end.gt(code))
+                    .filter(next_code.gt(code)),
+            )
+            .set((
+                next_code.eq(code),
+                worker.eq(None::<String>),
+                leased_until.eq(None::<chrono::NaiveDateTime>),
+                completed_at.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .execute(conn)?;
+            Ok(())
+        })?;
+        self.set_current_experience(code)
+    }
+
+    /// Replace the work the host has in flight
+    pub fn save_dispatched_work(&mut self, work: Vec<DispatchedWork>) -> Result<()> {
+        self.conn()?.transaction::<_, ExplorerError, _>(|conn| {
+            diesel::delete(dispatched_work::table).execute(conn)?;
+            diesel::insert_into(dispatched_work::table)
+                .values(&work)
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Work the host saved when it last stopped
+    pub fn dispatched_work(&mut self) -> Result<Vec<DispatchedWork>> {
+        Ok(dispatched_work::table
+            .order(dispatched_work::code)
+            .select(DispatchedWork::as_select())
+            .load(&mut self.conn()?)?)
+    }
+}
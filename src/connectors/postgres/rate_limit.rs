@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use diesel::{
+    dsl::sql,
+    prelude::*,
+    sql_types::{Double, Integer},
+};
+
+use crate::connectors::postgres::schema::rate_limits::dsl::*;
+
+use crate::error::{ExplorerError, Result};
+
+use super::{lib::PostgresClient, models::RateLimit};
+
+/// Lock the bucket `_name` for the rest of the transaction, created full when it doesn't exist
+fn lock_bucket(
+    conn: &mut PgConnection,
+    _name: &str,
+    burst: f64,
+    now: NaiveDateTime,
+) -> Result<RateLimit> {
+    diesel::insert_into(rate_limits)
+        .values(&RateLimit {
+            name: _name.to_string(),
+            tokens: burst,
+            updated_at: now,
+            rate_factor: 1.0,
+            backoff_level: 0,
+            backoff_until: None,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(rate_limits
+        .find(_name)
+        .select(RateLimit::as_select())
+        .for_update()
+        .first(conn)?)
+}
+
+impl PostgresClient {
+    /// Take a token from the bucket `_name`, refilled with `per_minute` tokens up to `burst`.
+    /// Returns how long to wait for the next token when it's empty or backing off
+    pub fn take_token(
+        &mut self,
+        _name: &str,
+        per_minute: f64,
+        burst: f64,
+    ) -> Result<Option<Duration>> {
+        let now = Utc::now().naive_utc();
+        self.conn()?.transaction::<_, ExplorerError, _>(|conn| {
+            let bucket = lock_bucket(conn, _name, burst, now)?;
+            if let Some(until) = bucket.backoff_until.filter(|until| *until > now) {
+                return Ok(Some((until - now).to_std()?));
+            }
+
+            // clocks of other processes can be a bit ahead
+            let per_minute = per_minute * bucket.rate_factor;
+            let elapsed = (now - bucket.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+            let available = (bucket.tokens + elapsed * per_minute / 60.0).min(burst);
+            let (left, wait) = match available >= 1.0 {
+                true => (available - 1.0, None),
+                false => (
+                    available,
+                    Some(Duration::from_secs_f64(
+                        (1.0 - available) * 60.0 / per_minute,
+                    )),
+                ),
+            };
+
+            diesel::update(rate_limits.find(_name))
+                .set((tokens.eq(left), updated_at.eq(now.max(bucket.updated_at))))
+                .execute(conn)?;
+            Ok(wait)
+        })
+    }
+
+    /// Stop all requests on the bucket for `delay(level)` and halve its rate down to `min_factor`.
+    /// Reports while a backoff is running don't extend it, returns how long it lasts
+    pub fn throttle_bucket(
+        &mut self,
+        _name: &str,
+        burst: f64,
+        min_factor: f64,
+        delay: impl Fn(i32) -> Duration,
+    ) -> Result<Duration> {
+        let now = Utc::now().naive_utc();
+        self.conn()?.transaction::<_, ExplorerError, _>(|conn| {
+            let bucket = lock_bucket(conn, _name, burst, now)?;
+            if let Some(until) = bucket.backoff_until.filter(|until| *until > now) {
+                return Ok((until - now).to_std()?);
+            }
+
+            let wait = delay(bucket.backoff_level);
+            diesel::update(rate_limits.find(_name))
+                .set((
+                    tokens.eq(0.0),
+                    updated_at.eq(now),
+                    rate_factor.eq((bucket.rate_factor / 2.0).max(min_factor)),
+                    backoff_level.eq(bucket.backoff_level + 1),
+                    backoff_until.eq(now + TimeDelta::from_std(wait)?),
+                ))
+                .execute(conn)?;
+            Ok(wait)
+        })
+    }
+
+    /// Raise the rate of a throttled bucket by `step`, forgets the throttles once it's back at full rate
+    pub fn recover_bucket(&mut self, _name: &str, step: f64) -> Result<()> {
+        diesel::update(rate_limits.find(_name))
+            .filter(rate_factor.ne(1.0).or(backoff_level.gt(0)))
+            .set((
+                rate_factor.eq(sql::<Double>("LEAST(rate_factor + ")
+                    .bind::<Double, _>(step)
+                    .sql(", 1.0)")),
+                backoff_level.eq(sql::<Integer>("CASE WHEN rate_factor + ")
+                    .bind::<Double, _>(step)
+                    .sql(" >= 1.0 THEN 0 ELSE backoff_level END")),
+            ))
+            .execute(&mut self.conn()?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use diesel::prelude::*;
+
+use crate::connectors::postgres::schema::experience_rotations::dsl::*;
+
+use crate::error::Result;
+
+use super::{
+    lib::PostgresClient,
+    models::{Experience, ExperienceRotation},
+};
+
+/// Replace the stored rotation of `experience` with the one in its arrays
+pub fn sync_rotation(conn: &mut PgConnection, experience: &Experience) -> Result<()> {
+    diesel::delete(experience_rotations.filter(experience_id.eq(experience.experience_id)))
+        .execute(conn)?;
+    diesel::insert_into(experience_rotations)
+        .values(&ExperienceRotation::from_experience(experience))
+        .execute(conn)?;
+    Ok(())
+}
+
+impl PostgresClient {
+    /// The maps of an experience in the order they're played
+    pub fn experience_rotation(&mut self, _experience_id: i32) -> Result<Vec<ExperienceRotation>> {
+        Ok(experience_rotations
+            .filter(experience_id.eq(_experience_id))
+            .order(position.asc())
+            .select(ExperienceRotation::as_select())
+            .load(&mut self.conn()?)?)
+    }
+
+    /// Experiences that play `_map_name`, optionally only in `_mode` and at `_game_size`
+    pub fn experiences_with_map(
+        &mut self,
+        _map_name: &str,
+        _mode: Option<&str>,
+        _game_size: Option<i32>,
+    ) -> Result<Vec<i32>> {
+        let mut query = experience_rotations
+            .filter(map_name.eq(_map_name))
+            .select(experience_id)
+            .distinct()
+            .into_boxed();
+        if let Some(_mode) = _mode {
+            query = query.filter(mode.eq(_mode));
+        }
+        if let Some(_game_size) = _game_size {
+            query = query.filter(game_size.eq(_game_size));
+        }
+        Ok(query.order(experience_id).load(&mut self.conn()?)?)
+    }
+}
@@ -1,5 +1,29 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    crawl_attempts (experience_id) {
+        experience_id -> Int4,
+        #[max_length = 32]
+        outcome -> Varchar,
+        attempt_count -> Int4,
+        last_error -> Nullable<Text>,
+        first_attempt_at -> Timestamp,
+        last_attempt_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    crawl_leases (range_start) {
+        range_start -> Int4,
+        range_end -> Int4,
+        next_code -> Int4,
+        #[max_length = 64]
+        worker -> Nullable<Varchar>,
+        leased_until -> Nullable<Timestamp>,
+        completed_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     current_experiences (id) {
         id -> Int4,
@@ -7,6 +31,63 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    dead_letters (experience_id) {
+        experience_id -> Int4,
+        attempts -> Int4,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    dispatched_work (code) {
+        code -> Int4,
+        #[max_length = 16]
+        kind -> Varchar,
+        attempt -> Int4,
+        saved_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    experience_rotations (experience_id, position) {
+        experience_id -> Int4,
+        position -> Int4,
+        map_name -> Nullable<Text>,
+        mode -> Nullable<Text>,
+        game_size -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    experience_tags (experience_id, tag_id) {
+        experience_id -> Int4,
+        tag_id -> Int4,
+    }
+}
+
+diesel::table! {
+    experience_versions (experience_id, version) {
+        experience_id -> Int4,
+        version -> Int4,
+        #[max_length = 64]
+        content_hash -> Varchar,
+        #[max_length = 255]
+        playground_name -> Varchar,
+        playground_description -> Text,
+        playground_data -> Jsonb,
+        tags -> Jsonb,
+        progression_mode -> Jsonb,
+        maps -> Array<Nullable<Text>>,
+        game_sizes -> Array<Nullable<Int4>>,
+        modes -> Array<Nullable<Text>>,
+        playground_updated_at -> Timestamp,
+        created_at -> Timestamp,
+        changes -> Nullable<Jsonb>,
+    }
+}
+
 diesel::table! {
     experiences (experience_id) {
         experience_id -> Int4,
@@ -25,10 +106,45 @@ diesel::table! {
         modes -> Array<Nullable<Text>>,
         maps -> Array<Nullable<Text>>,
         game_sizes -> Array<Nullable<Int4>>,
+        last_seen_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    rate_limits (name) {
+        #[max_length = 64]
+        name -> Varchar,
+        tokens -> Float8,
+        updated_at -> Timestamp,
+        rate_factor -> Float8,
+        backoff_level -> Int4,
+        backoff_until -> Nullable<Timestamp>,
     }
 }
 
+diesel::table! {
+    tags (id) {
+        id -> Int4,
+        #[max_length = 255]
+        name -> Varchar,
+        category -> Nullable<Text>,
+        first_seen -> Timestamp,
+    }
+}
+
+diesel::joinable!(experience_tags -> tags (tag_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    crawl_attempts,
+    crawl_leases,
     current_experiences,
+    dead_letters,
+    dispatched_work,
+    experience_rotations,
+    experience_tags,
+    experience_versions,
     experiences,
+    rate_limits,
+    tags,
 );
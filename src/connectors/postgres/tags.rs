@@ -0,0 +1,80 @@
+use chrono::NaiveDateTime;
+use diesel::{dsl::count_star, prelude::*};
+
+use crate::connectors::postgres::schema::{experience_tags, tags};
+
+use crate::error::Result;
+
+use super::{
+    lib::PostgresClient,
+    models::{Experience, ExperienceTag, NewTag, Tag},
+};
+
+/// Add the tags of `experience` to the catalog and replace the ones linked to it
+pub fn sync_tags(conn: &mut PgConnection, experience: &Experience) -> Result<()> {
+    let found = NewTag::from_experience(experience);
+    diesel::insert_into(tags::table)
+        .values(&found)
+        .on_conflict(tags::name)
+        .do_nothing()
+        .execute(conn)?;
+    let tag_ids: Vec<i32> = tags::table
+        .filter(tags::name.eq_any(found.iter().map(|tag| &tag.name)))
+        .select(tags::id)
+        .load(conn)?;
+
+    diesel::delete(
+        experience_tags::table.filter(experience_tags::experience_id.eq(experience.experience_id)),
+    )
+    .execute(conn)?;
+    let linked: Vec<ExperienceTag> = tag_ids
+        .into_iter()
+        .map(|tag_id| ExperienceTag {
+            experience_id: experience.experience_id,
+            tag_id,
+        })
+        .collect();
+    diesel::insert_into(experience_tags::table)
+        .values(&linked)
+        .execute(conn)?;
+    Ok(())
+}
+
+impl PostgresClient {
+    pub fn experience_tags(&mut self, _experience_id: i32) -> Result<Vec<Tag>> {
+        Ok(experience_tags::table
+            .inner_join(tags::table)
+            .filter(experience_tags::experience_id.eq(_experience_id))
+            .order(tags::name)
+            .select(Tag::as_select())
+            .load(&mut self.conn()?)?)
+    }
+
+    /// Every tag with the amount of experiences that have it, the most used first
+    pub fn tag_counts(&mut self) -> Result<Vec<(Tag, i64)>> {
+        Ok(experience_tags::table
+            .inner_join(tags::table)
+            .group_by(tags::id)
+            .select((Tag::as_select(), count_star()))
+            .order(count_star().desc())
+            .load(&mut self.conn()?)?)
+    }
+
+    /// Tags EA introduced since `since`
+    pub fn tags_seen_since(&mut self, since: NaiveDateTime) -> Result<Vec<Tag>> {
+        Ok(tags::table
+            .filter(tags::first_seen.ge(since))
+            .order(tags::first_seen.desc())
+            .select(Tag::as_select())
+            .load(&mut self.conn()?)?)
+    }
+
+    pub fn experiences_with_tag(&mut self, _name: &str) -> Result<Vec<i32>> {
+        Ok(experience_tags::table
+            .inner_join(tags::table)
+            .filter(tags::name.eq(_name))
+            .select(experience_tags::experience_id)
+            .order(experience_tags::experience_id)
+            .load(&mut self.conn()?)?)
+    }
+}
@@ -0,0 +1,141 @@
+use diesel::prelude::*;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::connectors::postgres::schema::experience_versions::dsl::*;
+use crate::connectors::postgres::schema::experiences;
+
+use crate::error::Result;
+
+use super::{
+    diff::ExperienceDiff,
+    lib::PostgresClient,
+    models::{Experience, ExperienceVersion},
+};
+
+/// Write `value` with the keys of every object sorted, Postgres doesn't keep
+/// the order of jsonb keys so the stored data has to hash the same as a fresh response
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        value => out.push_str(&value.to_string()),
+    }
+}
+
+/// Hex sha256 of the playground data
+pub fn content_hash_of(data: &Value) -> String {
+    let mut canonical = String::new();
+    write_canonical(data, &mut canonical);
+    format!("{:x}", Sha256::digest(canonical.as_bytes()))
+}
+
+/// Store `experience` as a new version when its playground data changed,
+/// with what changed since the version before it. Returns the number of the version it added.
+///
+/// Experiences stored before the history existed get their stored row as version 1 first
+pub fn store_version(conn: &mut PgConnection, experience: &Experience) -> Result<Option<i32>> {
+    let mut latest: Option<ExperienceVersion> = experience_versions
+        .filter(experience_id.eq(experience.experience_id))
+        .order(version.desc())
+        .select(ExperienceVersion::as_select())
+        .first(conn)
+        .optional()?;
+
+    if latest.is_none() {
+        let stored: Option<Experience> = experiences::table
+            .find(experience.experience_id)
+            .select(Experience::as_select())
+            .first(conn)
+            .optional()?;
+        if let Some(stored) = stored {
+            let hash = content_hash_of(&stored.playground_data);
+            let first = ExperienceVersion::snapshot(&stored, 1, hash);
+            diesel::insert_into(experience_versions)
+                .values(&first)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            latest = Some(first);
+        }
+    }
+
+    let hash = content_hash_of(&experience.playground_data);
+    let snapshot = match latest {
+        Some(latest) if latest.content_hash == hash => return Ok(None),
+        Some(latest) => {
+            let mut snapshot = ExperienceVersion::snapshot(experience, latest.version + 1, hash);
+            snapshot.changes = Some(serde_json::to_value(ExperienceDiff::versions(
+                &latest, &snapshot,
+            ))?);
+            snapshot
+        }
+        None => ExperienceVersion::snapshot(experience, 1, hash),
+    };
+    // someone else storing the same experience at once already added it
+    let added = diesel::insert_into(experience_versions)
+        .values(&snapshot)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok((added > 0).then_some(snapshot.version))
+}
+
+impl PostgresClient {
+    /// Every stored version of an experience, the oldest first
+    pub fn experience_versions(&mut self, _experience_id: i32) -> Result<Vec<ExperienceVersion>> {
+        Ok(experience_versions
+            .filter(experience_id.eq(_experience_id))
+            .order(version.asc())
+            .select(ExperienceVersion::as_select())
+            .load(&mut self.conn()?)?)
+    }
+
+    pub fn experience_version(
+        &mut self,
+        _experience_id: i32,
+        _version: i32,
+    ) -> Result<Option<ExperienceVersion>> {
+        Ok(experience_versions
+            .find((_experience_id, _version))
+            .select(ExperienceVersion::as_select())
+            .first(&mut self.conn()?)
+            .optional()?)
+    }
+
+    /// What changed between any two stored versions of an experience,
+    /// None if one of them doesn't exist
+    pub fn diff_versions(
+        &mut self,
+        _experience_id: i32,
+        from: i32,
+        to: i32,
+    ) -> Result<Option<ExperienceDiff>> {
+        let from = self.experience_version(_experience_id, from)?;
+        let to = self.experience_version(_experience_id, to)?;
+        Ok(match (from, to) {
+            (Some(from), Some(to)) => Some(ExperienceDiff::versions(&from, &to)),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+
+use crate::error::Result;
+
+pub type DeliveryStream = BoxStream<'static, Result<Delivery>>;
+
+/// Queue operations the host and workers need, so they can run on RabbitMQ or in-process
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Durable queue shared by all workers
+    async fn declare_que(&self, name: &str) -> Result<()>;
+    /// Queue that is removed when its worker disconnects
+    async fn declare_que_worker(&self, name: &str) -> Result<()>;
+    /// Queue that holds messages for `delay` before moving them to `target`
+    async fn declare_retry_que(&self, name: &str, target: &str, delay: Duration) -> Result<()>;
+    async fn delete_que(&self, name: &str) -> Result<()>;
+    /// Amount of messages waiting in the queue, not counting the unacked ones
+    async fn que_depth(&self, name: &str) -> Result<u32>;
+    async fn publish(&self, que: &str, value: String) -> Result<()>;
+    /// Max amount of unacked deliveries per consumer
+    async fn set_qos(&self, prefetch: u16) -> Result<()>;
+    async fn consume(&self, que: &str) -> Result<DeliveryStream>;
+    async fn close(&self) -> Result<()>;
+}
+
+#[async_trait]
+pub trait Acker: Send + Sync {
+    async fn ack(&self) -> Result<()>;
+    async fn nack(&self, requeue: bool) -> Result<()>;
+}
+
+/// Message taken from a queue, has to be acked or nacked
+pub struct Delivery {
+    pub data: Vec<u8>,
+    acker: Box<dyn Acker>,
+}
+
+impl Delivery {
+    pub fn new(data: Vec<u8>, acker: Box<dyn Acker>) -> Self {
+        Self { data, acker }
+    }
+
+    pub async fn ack(&self) -> Result<()> {
+        self.acker.ack().await
+    }
+
+    /// Give the message back to the queue, or drop it when `requeue` is false
+    pub async fn nack(&self, requeue: bool) -> Result<()> {
+        self.acker.nack(requeue).await
+    }
+}
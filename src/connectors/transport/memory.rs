@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use tokio::sync::mpsc;
+
+use super::lib::{Acker, Delivery, DeliveryStream, Transport};
+use crate::error::{ExplorerError, Result};
+
+type Receiver = Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Vec<u8>>>>;
+
+#[derive(Clone)]
+struct MemoryQueue {
+    sender: mpsc::UnboundedSender<Vec<u8>>,
+    /// Shared by all consumers, each message goes to a single one of them
+    receiver: Receiver,
+    /// Messages sent and not taken by a consumer yet
+    depth: Arc<AtomicU32>,
+    /// Retry queues forward their messages to another queue after a delay
+    forward: Option<(String, Duration)>,
+}
+
+impl MemoryQueue {
+    fn new(forward: Option<(String, Duration)>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            sender,
+            receiver: Arc::new(tokio::sync::Mutex::new(receiver)),
+            depth: Arc::new(AtomicU32::new(0)),
+            forward,
+        }
+    }
+
+    fn send(&self, data: Vec<u8>) -> Result<()> {
+        // counted first, a consumer can take the message before send returns
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        self.sender
+            .send(data)
+            .map_err(|_| ExplorerError::Queue("queue was dropped".to_string()))?;
+        Ok(())
+    }
+}
+
+/// In-process transport on tokio channels, to run the host and workers without a broker.
+///
+/// Queues are created when first used, qos is ignored. Deliveries that weren't acked go back
+/// to their queue once dropped, like when a worker stops or closes the transport mid-item.
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    queues: Arc<Mutex<HashMap<String, MemoryQueue>>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn queue(&self, name: &str) -> MemoryQueue {
+        self.queues
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| MemoryQueue::new(None))
+            .clone()
+    }
+}
+
+/// Like RabbitMQ, a delivery that is dropped without an ack or nack goes back to the queue
+struct MemoryAcker {
+    queue: MemoryQueue,
+    data: Vec<u8>,
+    settled: AtomicBool,
+}
+
+#[async_trait]
+impl Acker for MemoryAcker {
+    async fn ack(&self) -> Result<()> {
+        self.settled.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn nack(&self, requeue: bool) -> Result<()> {
+        if !self.settled.swap(true, Ordering::Relaxed) && requeue {
+            self.queue.send(self.data.clone())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MemoryAcker {
+    fn drop(&mut self) {
+        if !self.settled.load(Ordering::Relaxed) {
+            let _ = self.queue.send(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn declare_que(&self, name: &str) -> Result<()> {
+        self.queue(name);
+        Ok(())
+    }
+
+    async fn declare_que_worker(&self, name: &str) -> Result<()> {
+        self.queue(name);
+        Ok(())
+    }
+
+    async fn declare_retry_que(&self, name: &str, target: &str, delay: Duration) -> Result<()> {
+        self.queues.lock().unwrap().insert(
+            name.to_string(),
+            MemoryQueue::new(Some((target.to_string(), delay))),
+        );
+        Ok(())
+    }
+
+    async fn delete_que(&self, name: &str) -> Result<()> {
+        self.queues.lock().unwrap().remove(name);
+        Ok(())
+    }
+
+    async fn que_depth(&self, name: &str) -> Result<u32> {
+        Ok(self.queue(name).depth.load(Ordering::Relaxed))
+    }
+
+    async fn publish(&self, que: &str, value: String) -> Result<()> {
+        let queue = self.queue(que);
+        match queue.forward {
+            Some((target, delay)) => {
+                let transport = self.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if let Err(e) = transport.publish(&target, value).await {
+                        log::error!("couldn't forward delayed message to {}: {:#}", target, e);
+                    }
+                });
+            }
+            None => queue.send(value.into_bytes())?,
+        }
+        Ok(())
+    }
+
+    async fn set_qos(&self, _prefetch: u16) -> Result<()> {
+        Ok(())
+    }
+
+    async fn consume(&self, que: &str) -> Result<DeliveryStream> {
+        let queue = self.queue(que);
+        Ok(futures::stream::unfold(queue, |queue| async move {
+            let data = queue.receiver.lock().await.recv().await?;
+            queue.depth.fetch_sub(1, Ordering::Relaxed);
+            let delivery = Delivery::new(
+                data.clone(),
+                Box::new(MemoryAcker {
+                    queue: queue.clone(),
+                    data,
+                    settled: AtomicBool::new(false),
+                }),
+            );
+            Some((Ok(delivery), queue))
+        })
+        .boxed())
+    }
+
+    async fn close(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn next(stream: &mut DeliveryStream) -> Delivery {
+        tokio::time::timeout(Duration::from_secs(1), stream.next())
+            .await
+            .expect("no delivery in time")
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn is_empty(stream: &mut DeliveryStream) -> bool {
+        tokio::time::timeout(Duration::from_millis(50), stream.next())
+            .await
+            .is_err()
+    }
+
+    #[tokio::test]
+    async fn messages_are_consumed_in_order() {
+        let transport = MemoryTransport::new();
+        transport.declare_que("work").await.unwrap();
+        transport.publish("work", "1".to_string()).await.unwrap();
+        transport.publish("work", "2".to_string()).await.unwrap();
+        assert_eq!(transport.que_depth("work").await.unwrap(), 2);
+
+        let mut stream = transport.consume("work").await.unwrap();
+        for expected in ["1", "2"] {
+            let delivery = next(&mut stream).await;
+            assert_eq!(delivery.data, expected.as_bytes());
+            delivery.ack().await.unwrap();
+        }
+        assert_eq!(transport.que_depth("work").await.unwrap(), 0);
+        assert!(is_empty(&mut stream).await);
+    }
+
+    #[tokio::test]
+    async fn nack_requeues_only_when_asked() {
+        let transport = MemoryTransport::new();
+        let mut stream = transport.consume("work").await.unwrap();
+        transport.publish("work", "1".to_string()).await.unwrap();
+
+        next(&mut stream).await.nack(true).await.unwrap();
+        let delivery = next(&mut stream).await;
+        assert_eq!(delivery.data, b"1");
+
+        delivery.nack(false).await.unwrap();
+        drop(delivery);
+        assert!(is_empty(&mut stream).await);
+    }
+
+    #[tokio::test]
+    async fn dropped_deliveries_go_back_to_the_queue() {
+        let transport = MemoryTransport::new();
+        let mut stream = transport.consume("work").await.unwrap();
+        transport.publish("work", "1".to_string()).await.unwrap();
+
+        drop(next(&mut stream).await);
+        assert_eq!(transport.que_depth("work").await.unwrap(), 1);
+        let delivery = next(&mut stream).await;
+        assert_eq!(delivery.data, b"1");
+
+        delivery.ack().await.unwrap();
+        drop(delivery);
+        assert!(is_empty(&mut stream).await);
+    }
+
+    #[tokio::test]
+    async fn retry_queues_forward_after_their_delay() {
+        let transport = MemoryTransport::new();
+        let delay = Duration::from_millis(100);
+        transport
+            .declare_retry_que("work_retry", "work", delay)
+            .await
+            .unwrap();
+        let mut stream = transport.consume("work").await.unwrap();
+
+        let sent = tokio::time::Instant::now();
+        transport
+            .publish("work_retry", "1".to_string())
+            .await
+            .unwrap();
+        assert_eq!(transport.que_depth("work").await.unwrap(), 0);
+
+        let delivery = next(&mut stream).await;
+        assert_eq!(delivery.data, b"1");
+        assert!(sent.elapsed() >= delay);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod lib;
+pub mod memory;
+pub mod rabbit;
@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use lapin::{
+    options::{BasicAckOptions, BasicNackOptions, BasicQosOptions},
+    Channel,
+};
+
+use super::lib::{Acker, Delivery, DeliveryStream, Transport};
+use crate::{
+    connectors::{ampq, health::Health},
+    error::Result,
+};
+
+/// Transport on a RabbitMQ channel, opens a new one when the connection dropped
+pub struct RabbitTransport {
+    channel: tokio::sync::Mutex<Channel>,
+    health: Health,
+}
+
+impl RabbitTransport {
+    /// Waits until the broker can be reached
+    pub async fn connect(health: &Health) -> Result<Self> {
+        Ok(Self {
+            channel: tokio::sync::Mutex::new(ampq::connect_channel(health).await?),
+            health: health.clone(),
+        })
+    }
+
+    async fn channel(&self) -> Result<Channel> {
+        let mut channel = self.channel.lock().await;
+        if !channel.status().connected() {
+            log::warn!("rabbitmq channel is closed, reconnecting");
+            self.health.degrade("rabbitmq", "channel closed");
+            *channel = ampq::connect_channel(&self.health).await?;
+        }
+        Ok(channel.clone())
+    }
+}
+
+struct RabbitAcker(lapin::acker::Acker);
+
+#[async_trait]
+impl Acker for RabbitAcker {
+    async fn ack(&self) -> Result<()> {
+        self.0.ack(BasicAckOptions::default()).await?;
+        Ok(())
+    }
+
+    async fn nack(&self, requeue: bool) -> Result<()> {
+        self.0
+            .nack(BasicNackOptions {
+                requeue,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transport for RabbitTransport {
+    async fn declare_que(&self, name: &str) -> Result<()> {
+        ampq::declare_que(&self.channel().await?, name).await
+    }
+
+    async fn declare_que_worker(&self, name: &str) -> Result<()> {
+        ampq::declare_que_worker(&self.channel().await?, name).await
+    }
+
+    async fn declare_retry_que(&self, name: &str, target: &str, delay: Duration) -> Result<()> {
+        ampq::declare_retry_que(&self.channel().await?, name, target, delay).await
+    }
+
+    async fn delete_que(&self, name: &str) -> Result<()> {
+        ampq::delete_que(&self.channel().await?, name).await
+    }
+
+    async fn que_depth(&self, name: &str) -> Result<u32> {
+        ampq::que_depth(&self.channel().await?, name).await
+    }
+
+    async fn publish(&self, que: &str, value: String) -> Result<()> {
+        ampq::publish(&self.channel().await?, que, value).await
+    }
+
+    async fn set_qos(&self, prefetch: u16) -> Result<()> {
+        self.channel()
+            .await?
+            .basic_qos(prefetch, BasicQosOptions::default())
+            .await?;
+        Ok(())
+    }
+
+    async fn consume(&self, que: &str) -> Result<DeliveryStream> {
+        let consumer = ampq::new_consumer(&self.channel().await?, que).await?;
+        Ok(consumer
+            .map(|delivery| {
+                let delivery = delivery?;
+                Ok(Delivery::new(
+                    delivery.data,
+                    Box::new(RabbitAcker(delivery.acker)),
+                ))
+            })
+            .boxed())
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.channel
+            .lock()
+            .await
+            .close(200, "Normal shutdown")
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Keeps track of the experience codes that are handed out to workers.
+///
+/// Everything below `cursor` is done, codes between `cursor` and `next_code`
+/// are either in flight or already completed out of order.
+pub struct DispatchWindow {
+    size: usize,
+    cursor: i32,
+    next_code: i32,
+    in_flight: BTreeSet<i32>,
+    /// Codes completed out of order, with whether they existed
+    completed: BTreeMap<i32, Option<bool>>,
+    /// Failed attempts of codes that are still in flight
+    failures: HashMap<i32, u32>,
+}
+
+impl DispatchWindow {
+    pub fn new(cursor: i32, size: usize) -> Self {
+        Self {
+            size: size.max(1),
+            cursor,
+            next_code: cursor,
+            in_flight: BTreeSet::new(),
+            completed: BTreeMap::new(),
+            failures: HashMap::new(),
+        }
+    }
+
+    /// First code that hasn't been completed, safe to persist
+    pub fn cursor(&self) -> i32 {
+        self.cursor
+    }
+
+    pub fn is_in_flight(&self, code: i32) -> bool {
+        self.in_flight.contains(&code)
+    }
+
+    /// Put a code that was handed out before a restart back in flight
+    pub fn resume(&mut self, code: i32, failures: u32) {
+        if code < self.cursor || self.completed.contains_key(&code) {
+            return;
+        }
+        self.in_flight.insert(code);
+        if failures > 0 {
+            self.failures.insert(code, failures);
+        }
+        self.next_code = self.next_code.max(code + 1);
+    }
+
+    /// Codes in flight with their failed attempts so far
+    pub fn in_flight(&self) -> Vec<(i32, u32)> {
+        self.in_flight
+            .iter()
+            .map(|code| (*code, self.failures.get(code).copied().unwrap_or_default()))
+            .collect()
+    }
+
+    /// Hand out new codes until the window is full
+    pub fn fill(&mut self) -> Vec<i32> {
+        let mut codes = vec![];
+        while self.in_flight.len() < self.size {
+            self.in_flight.insert(self.next_code);
+            codes.push(self.next_code);
+            self.next_code += 1;
+        }
+        codes
+    }
+
+    /// Count a failed attempt for a code in flight, returns the total failures
+    pub fn fail(&mut self, code: i32) -> u32 {
+        let failures = self.failures.entry(code).or_default();
+        *failures += 1;
+        *failures
+    }
+
+    /// Mark a code as done, returns the codes the cursor moved past in order.
+    /// `found` is None for dead-lettered codes, it's unknown whether those exist
+    pub fn complete(&mut self, code: i32, found: Option<bool>) -> Vec<(i32, Option<bool>)> {
+        if !self.in_flight.remove(&code) {
+            return vec![];
+        }
+        self.failures.remove(&code);
+        self.completed.insert(code, found);
+
+        let mut advanced = vec![];
+        while let Some(found) = self.completed.remove(&self.cursor) {
+            advanced.push((self.cursor, found));
+            self.cursor += 1;
+        }
+        advanced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_hands_out_the_next_codes() {
+        let mut window = DispatchWindow::new(10, 3);
+        assert_eq!(window.fill(), [10, 11, 12]);
+        assert!(window.fill().is_empty());
+        assert_eq!(window.cursor(), 10);
+    }
+
+    #[test]
+    fn cursor_waits_for_the_lowest_code() {
+        let mut window = DispatchWindow::new(10, 3);
+        window.fill();
+
+        assert!(window.complete(12, Some(true)).is_empty());
+        assert!(window.complete(11, None).is_empty());
+        assert_eq!(window.cursor(), 10);
+        assert_eq!(window.fill(), [13, 14]);
+
+        assert_eq!(
+            window.complete(10, Some(false)),
+            [(10, Some(false)), (11, None), (12, Some(true))]
+        );
+        assert_eq!(window.cursor(), 13);
+        assert_eq!(window.fill(), [15]);
+    }
+
+    #[test]
+    fn codes_complete_once() {
+        let mut window = DispatchWindow::new(10, 2);
+        window.fill();
+        assert!(window.complete(42, Some(true)).is_empty());
+        assert_eq!(window.complete(10, Some(true)), [(10, Some(true))]);
+        assert!(window.complete(10, Some(true)).is_empty());
+        assert_eq!(window.cursor(), 11);
+    }
+
+    #[test]
+    fn failures_are_counted_until_complete() {
+        let mut window = DispatchWindow::new(10, 2);
+        window.fill();
+        assert_eq!(window.fail(11), 1);
+        assert_eq!(window.fail(11), 2);
+        assert_eq!(window.in_flight(), [(10, 0), (11, 2)]);
+
+        window.complete(11, None);
+        assert_eq!(window.in_flight(), [(10, 0)]);
+    }
+
+    #[test]
+    fn resume_skips_finished_codes() {
+        let mut window = DispatchWindow::new(10, 3);
+        window.resume(9, 0);
+        window.resume(12, 2);
+        assert_eq!(window.in_flight(), [(12, 2)]);
+        // fills up after the highest resumed code
+        assert_eq!(window.fill(), [13, 14]);
+    }
+}
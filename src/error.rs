@@ -0,0 +1,222 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tonic::Code;
+
+use crate::connectors::postgres::models::CrawlOutcome;
+
+/// Why something failed, the clients and connectors map their errors into this so
+/// callers can tell what happened without looking at the text.
+///
+/// Sent along with work results, so the host can decide what to do with the code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum ExplorerError {
+    /// The code has no experience
+    NotFound,
+    /// The Kingston session or EA access token was rejected
+    Unauthorized(String),
+    /// Asked to slow down
+    Throttled(String),
+    /// gRPC or HTTP request that got no usable answer
+    Transport(String),
+    /// An answer that couldn't be read
+    InvalidResponse(String),
+    /// A Postgres query failed or no connection was available
+    Database(String),
+    Mongo(String),
+    /// RabbitMQ
+    Queue(String),
+    /// Configuration errors and bugs, trying again won't help
+    Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, ExplorerError>;
+
+impl ExplorerError {
+    /// Sort an error of the Kingston client by the gRPC status it failed with,
+    /// anything without a known status is a transport error
+    pub fn from_kingston(error: anyhow::Error) -> Self {
+        let message = format!("{:#}", error);
+        let code = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<tonic::Status>())
+            .map(tonic::Status::code);
+
+        match code {
+            Some(Code::ResourceExhausted) => ExplorerError::Throttled(message),
+            Some(Code::Unauthenticated) | Some(Code::PermissionDenied) => {
+                ExplorerError::Unauthorized(message)
+            }
+            // answered with an error instead of an empty playground
+            Some(Code::NotFound) => ExplorerError::NotFound,
+            _ => ExplorerError::Transport(message),
+        }
+    }
+
+    pub fn other(message: impl fmt::Display) -> Self {
+        ExplorerError::Other(message.to_string())
+    }
+
+    /// Whether the same request can succeed later
+    pub fn retryable(&self) -> bool {
+        match self {
+            ExplorerError::NotFound
+            | ExplorerError::InvalidResponse(_)
+            | ExplorerError::Other(_) => false,
+            ExplorerError::Unauthorized(_)
+            | ExplorerError::Throttled(_)
+            | ExplorerError::Transport(_)
+            | ExplorerError::Database(_)
+            | ExplorerError::Mongo(_)
+            | ExplorerError::Queue(_) => true,
+        }
+    }
+
+    /// Name used for logs and the error counters of the heartbeat
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExplorerError::NotFound => "not_found",
+            ExplorerError::Unauthorized(_) => "unauthorized",
+            ExplorerError::Throttled(_) => "throttled",
+            ExplorerError::Transport(_) => "transport",
+            ExplorerError::InvalidResponse(_) => "invalid_response",
+            ExplorerError::Database(_) => "database",
+            ExplorerError::Mongo(_) => "mongo",
+            ExplorerError::Queue(_) => "queue",
+            ExplorerError::Other(_) => "other",
+        }
+    }
+
+    /// What goes in the crawl ledger
+    pub fn outcome(&self) -> CrawlOutcome {
+        match self {
+            ExplorerError::NotFound => CrawlOutcome::NotFound,
+            ExplorerError::Throttled(_) => CrawlOutcome::Throttled,
+            ExplorerError::Unauthorized(_) => CrawlOutcome::Unauthorized,
+            _ => CrawlOutcome::Error,
+        }
+    }
+}
+
+impl fmt::Display for ExplorerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExplorerError::NotFound => write!(f, "not found"),
+            ExplorerError::Unauthorized(message)
+            | ExplorerError::Throttled(message)
+            | ExplorerError::Transport(message)
+            | ExplorerError::InvalidResponse(message)
+            | ExplorerError::Database(message)
+            | ExplorerError::Mongo(message)
+            | ExplorerError::Queue(message)
+            | ExplorerError::Other(message) => write!(f, "{}: {}", self.label(), message),
+        }
+    }
+}
+
+impl std::error::Error for ExplorerError {}
+
+/// Keeps the variant of an ExplorerError that went through anyhow
+impl From<anyhow::Error> for ExplorerError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<ExplorerError>() {
+            Ok(error) => error,
+            Err(error) => ExplorerError::Other(format!("{:#}", error)),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for ExplorerError {
+    fn from(error: diesel::result::Error) -> Self {
+        ExplorerError::Database(error.to_string())
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for ExplorerError {
+    fn from(error: diesel::r2d2::PoolError) -> Self {
+        ExplorerError::Database(error.to_string())
+    }
+}
+
+impl From<mongodb::error::Error> for ExplorerError {
+    fn from(error: mongodb::error::Error) -> Self {
+        ExplorerError::Mongo(error.to_string())
+    }
+}
+
+impl From<lapin::Error> for ExplorerError {
+    fn from(error: lapin::Error) -> Self {
+        ExplorerError::Queue(error.to_string())
+    }
+}
+
+impl From<reqwest::Error> for ExplorerError {
+    fn from(error: reqwest::Error) -> Self {
+        let message = error.to_string();
+        match error.status().map(|status| status.as_u16()) {
+            Some(429) => ExplorerError::Throttled(message),
+            Some(401) | Some(403) => ExplorerError::Unauthorized(message),
+            _ if error.is_decode() => ExplorerError::InvalidResponse(message),
+            _ => ExplorerError::Transport(message),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ExplorerError {
+    fn from(error: serde_json::Error) -> Self {
+        ExplorerError::InvalidResponse(error.to_string())
+    }
+}
+
+impl From<chrono::OutOfRangeError> for ExplorerError {
+    fn from(error: chrono::OutOfRangeError) -> Self {
+        ExplorerError::Other(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kingston_errors_are_sorted_by_status() {
+        let status = |status: tonic::Status| ExplorerError::from_kingston(status.into());
+        assert_eq!(
+            status(tonic::Status::not_found("no playground")),
+            ExplorerError::NotFound
+        );
+        assert!(matches!(
+            status(tonic::Status::resource_exhausted("slow down")),
+            ExplorerError::Throttled(_)
+        ));
+        assert!(matches!(
+            status(tonic::Status::unauthenticated("session expired")),
+            ExplorerError::Unauthorized(_)
+        ));
+        assert!(matches!(
+            status(tonic::Status::permission_denied("no access")),
+            ExplorerError::Unauthorized(_)
+        ));
+        assert!(matches!(
+            status(tonic::Status::unavailable("not found")),
+            ExplorerError::Transport(_)
+        ));
+    }
+
+    #[test]
+    fn kingston_status_is_found_behind_context() {
+        let error = anyhow::Error::from(tonic::Status::not_found("gone")).context("fetching 1");
+        assert_eq!(ExplorerError::from_kingston(error), ExplorerError::NotFound);
+    }
+
+    #[test]
+    fn kingston_text_is_not_matched() {
+        for message in ["playground not found", "authentication failed", "exhausted"] {
+            assert!(matches!(
+                ExplorerError::from_kingston(anyhow::anyhow!(message)),
+                ExplorerError::Transport(_)
+            ));
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::{env, time::Duration};
+
+/// Consecutive not-found codes before we start looking for the end of the code space
+const DEFAULT_MISS_THRESHOLD: u32 = 50;
+/// Amount of codes past the frontier that are polled in tail mode
+const DEFAULT_TAIL_WIDTH: i32 = 10;
+const DEFAULT_TAIL_POLL_SECS: u64 = 30;
+/// Largest code that fits an experience id, probes saturate at it instead of overflowing
+const MAX_CODE: i32 = i32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontierMode {
+    /// Walking the codes one by one
+    Sequential,
+    /// Jumping ahead from the last code that exists with a doubling step
+    Probing { found: i32, step: i32 },
+    /// Binary search between a code that exists and one that doesn't
+    Searching { found: i32, missing: i32 },
+    /// Past the newest experience, polling just after it for new ones
+    Tail { frontier: i32 },
+}
+
+/// Detects when the crawler walked past the newest experience.
+///
+/// Sequential results are fed with `observe`, while not in sequential mode the
+/// crawler checks `probe` codes and feeds them back with `observe_probe`.
+pub struct Frontier {
+    mode: FrontierMode,
+    miss_threshold: u32,
+    tail_width: i32,
+    tail_poll: Duration,
+    misses: u32,
+    /// Last sequential code that existed
+    last_found: i32,
+    /// Highest code found while probing, misses below it are gaps
+    known_frontier: i32,
+    tail_offset: i32,
+}
+
+impl Frontier {
+    /// Read FRONTIER_MISS_THRESHOLD, FRONTIER_TAIL_WIDTH and FRONTIER_TAIL_POLL_SECS, with defaults
+    pub fn from_env(cursor: i32) -> Self {
+        Self {
+            mode: FrontierMode::Sequential,
+            miss_threshold: env::var("FRONTIER_MISS_THRESHOLD")
+                .ok()
+                .and_then(|misses| misses.parse().ok())
+                .unwrap_or(DEFAULT_MISS_THRESHOLD)
+                .max(1),
+            tail_width: env::var("FRONTIER_TAIL_WIDTH")
+                .ok()
+                .and_then(|width| width.parse().ok())
+                .unwrap_or(DEFAULT_TAIL_WIDTH)
+                .max(1),
+            tail_poll: Duration::from_secs(
+                env::var("FRONTIER_TAIL_POLL_SECS")
+                    .ok()
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(DEFAULT_TAIL_POLL_SECS),
+            ),
+            misses: 0,
+            last_found: cursor - 1,
+            known_frontier: cursor - 1,
+            tail_offset: 0,
+        }
+    }
+
+    pub fn is_tailing(&self) -> bool {
+        matches!(self.mode, FrontierMode::Tail { .. })
+    }
+
+    /// How long to wait between polls in tail mode
+    pub fn tail_poll(&self) -> Duration {
+        self.tail_poll
+    }
+
+    /// Code to check next, None while crawling sequentially
+    pub fn probe(&self) -> Option<i32> {
+        match self.mode {
+            FrontierMode::Sequential => None,
+            FrontierMode::Probing { found, step } => Some(found.saturating_add(step)),
+            FrontierMode::Searching { found, missing } => Some(found + (missing - found) / 2),
+            FrontierMode::Tail { frontier } => Some(frontier.saturating_add(1 + self.tail_offset)),
+        }
+    }
+
+    /// Feed the result of a sequential code, in code order
+    pub fn observe(&mut self, code: i32, found: bool) {
+        if self.mode != FrontierMode::Sequential {
+            return;
+        }
+        if found {
+            self.misses = 0;
+            self.last_found = self.last_found.max(code);
+            return;
+        }
+
+        self.misses += 1;
+        if self.misses >= self.miss_threshold && code > self.known_frontier {
+            log::info!(
+                "{} codes not found after {}, probing for the frontier",
+                self.misses,
+                self.last_found
+            );
+            self.mode = FrontierMode::Probing {
+                found: self.last_found,
+                step: self.miss_threshold as i32 * 2,
+            };
+        }
+    }
+
+    /// Feed the result of a `probe` code.
+    /// Returns the code the sequential crawl has to restart from, if it changed
+    pub fn observe_probe(&mut self, code: i32, found: bool) -> Option<i32> {
+        if self.probe() != Some(code) {
+            return None;
+        }
+
+        match self.mode {
+            FrontierMode::Sequential => None,
+            FrontierMode::Probing { found: last, step } => {
+                self.mode = match found {
+                    // nothing past the last code, it has to be the frontier
+                    true if code == MAX_CODE => FrontierMode::Searching {
+                        found: code,
+                        missing: code,
+                    },
+                    true => FrontierMode::Probing {
+                        found: code,
+                        step: step.saturating_mul(2),
+                    },
+                    false => FrontierMode::Searching {
+                        found: last,
+                        missing: code,
+                    },
+                };
+                self.settle_search()
+            }
+            FrontierMode::Searching {
+                found: last,
+                missing,
+            } => {
+                self.mode = match found {
+                    true => FrontierMode::Searching {
+                        found: code,
+                        missing,
+                    },
+                    false => FrontierMode::Searching {
+                        found: last,
+                        missing: code,
+                    },
+                };
+                self.settle_search()
+            }
+            FrontierMode::Tail { frontier } => {
+                if !found {
+                    self.tail_offset = (self.tail_offset + 1) % self.tail_width;
+                    return None;
+                }
+                log::info!("new experience {} found past frontier {}", code, frontier);
+                self.mode = FrontierMode::Sequential;
+                self.misses = 0;
+                self.last_found = frontier;
+                self.known_frontier = code;
+                Some(frontier.saturating_add(1))
+            }
+        }
+    }
+
+    /// Give up on a probe that couldn't be checked, it's neither found nor missing.
+    /// The sequential crawl carries on and probes again after the next run of misses
+    pub fn abandon_probe(&mut self) {
+        match self.mode {
+            FrontierMode::Sequential => {}
+            FrontierMode::Tail { .. } => {
+                self.tail_offset = (self.tail_offset + 1) % self.tail_width;
+            }
+            FrontierMode::Probing { .. } | FrontierMode::Searching { .. } => {
+                log::info!(
+                    "probe failed, continuing sequential crawl after {}",
+                    self.last_found
+                );
+                self.mode = FrontierMode::Sequential;
+                self.misses = 0;
+            }
+        }
+    }
+
+    /// Leave search mode once the range is down to a single code,
+    /// tail mode restarts the sequential crawl just past the frontier
+    fn settle_search(&mut self) -> Option<i32> {
+        let frontier = match self.mode {
+            FrontierMode::Searching { found, missing } if missing - found <= 1 => found,
+            _ => return None,
+        };
+
+        self.misses = 0;
+        if frontier > self.last_found {
+            // only a gap of missing codes, keep walking towards the frontier
+            log::info!("frontier at {}, continuing sequential crawl", frontier);
+            self.known_frontier = frontier;
+            self.mode = FrontierMode::Sequential;
+        } else {
+            log::info!("frontier at {}, switching to tail mode", frontier);
+            self.tail_offset = 0;
+            self.mode = FrontierMode::Tail { frontier };
+            return Some(frontier + 1);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frontier(cursor: i32) -> Frontier {
+        Frontier {
+            miss_threshold: 3,
+            tail_width: 2,
+            ..Frontier::from_env(cursor)
+        }
+    }
+
+    fn miss(frontier: &mut Frontier, codes: std::ops::RangeInclusive<i32>) {
+        for code in codes {
+            frontier.observe(code, false);
+        }
+    }
+
+    #[test]
+    fn probes_after_the_miss_threshold() {
+        let mut frontier = frontier(10);
+        frontier.observe(10, true);
+        miss(&mut frontier, 11..=12);
+        frontier.observe(13, true);
+        miss(&mut frontier, 14..=15);
+        assert_eq!(frontier.probe(), None);
+
+        frontier.observe(16, false);
+        assert_eq!(frontier.mode, FrontierMode::Probing { found: 13, step: 6 });
+        assert_eq!(frontier.probe(), Some(19));
+    }
+
+    #[test]
+    fn search_over_a_gap_continues_the_crawl() {
+        let mut frontier = frontier(10);
+        frontier.observe(10, true);
+        miss(&mut frontier, 11..=13);
+
+        // 16 exists, 28 doesn't, the search ends on 23
+        for (probe, found) in [(16, true), (28, false), (22, true), (25, false), (23, true)] {
+            assert_eq!(frontier.probe(), Some(probe));
+            assert_eq!(frontier.observe_probe(probe, found), None);
+        }
+        assert_eq!(frontier.probe(), Some(24));
+        assert_eq!(frontier.observe_probe(24, false), None);
+        assert_eq!(frontier.mode, FrontierMode::Sequential);
+
+        // misses before the frontier that was found are a gap
+        miss(&mut frontier, 14..=23);
+        assert_eq!(frontier.probe(), None);
+        frontier.observe(24, false);
+        assert!(frontier.probe().is_some());
+    }
+
+    #[test]
+    fn search_without_anything_past_tails_the_frontier() {
+        let mut frontier = frontier(10);
+        frontier.observe(10, true);
+        miss(&mut frontier, 11..=13);
+
+        for (probe, found) in [(16, false), (13, false)] {
+            assert_eq!(frontier.probe(), Some(probe));
+            assert_eq!(frontier.observe_probe(probe, found), None);
+        }
+        assert_eq!(frontier.probe(), Some(11));
+        assert_eq!(frontier.observe_probe(11, false), Some(11));
+        assert!(frontier.is_tailing());
+
+        // polls the codes just past the frontier in turn
+        assert_eq!(frontier.observe_probe(11, false), None);
+        assert_eq!(frontier.probe(), Some(12));
+        assert_eq!(frontier.observe_probe(12, true), Some(11));
+        assert_eq!(frontier.mode, FrontierMode::Sequential);
+    }
+
+    #[test]
+    fn only_the_current_probe_counts() {
+        let mut frontier = frontier(10);
+        miss(&mut frontier, 10..=12);
+        assert_eq!(frontier.probe(), Some(15));
+        assert_eq!(frontier.observe_probe(14, true), None);
+        assert_eq!(frontier.probe(), Some(15));
+    }
+
+    #[test]
+    fn probes_saturate_at_the_last_code() {
+        let mut frontier = frontier(MAX_CODE - 4);
+        frontier.observe(MAX_CODE - 4, true);
+        miss(&mut frontier, MAX_CODE - 3..=MAX_CODE - 1);
+        assert_eq!(frontier.probe(), Some(MAX_CODE));
+
+        // everything up to the last code is a gap
+        assert_eq!(frontier.observe_probe(MAX_CODE, true), None);
+        assert_eq!(frontier.mode, FrontierMode::Sequential);
+        miss(&mut frontier, MAX_CODE - 3..=MAX_CODE - 1);
+        assert_eq!(frontier.probe(), None);
+    }
+
+    #[test]
+    fn abandoned_probes_return_to_the_crawl() {
+        let mut frontier = frontier(10);
+        miss(&mut frontier, 10..=12);
+        assert!(frontier.probe().is_some());
+
+        frontier.abandon_probe();
+        assert_eq!(frontier.probe(), None);
+        miss(&mut frontier, 13..=14);
+        assert_eq!(frontier.probe(), None);
+        frontier.observe(15, false);
+        assert!(frontier.probe().is_some());
+    }
+}
@@ -1,83 +1,251 @@
 mod clients;
 mod connectors;
+mod error;
 mod experience_code;
+mod frontier;
+mod rate_limit;
+mod refresh;
+mod retry;
+mod shutdown;
 
-use std::time::Duration;
+use std::env;
 
-use clients::standalone_client::StandaloneClient;
+use clients::{
+    playground_source::{self, PlaygroundSource},
+    recording::ReplaySource,
+};
 use connectors::{
+    health::{Backoff, Health},
     mongo::lib::MongoClient,
-    postgres::{lib::PostgresClient, models::Experience},
+    postgres::{lib::PostgresClient, models::CrawlOutcome},
 };
+use error::ExplorerError;
 use experience_code::ExperienceCode;
-use std::sync::{atomic, Arc};
-use tokio::time::sleep;
-use warp::Filter;
+use frontier::Frontier;
+use rate_limit::RateLimiter;
+use refresh::RefreshScheduler;
+use retry::RetryPolicy;
+use shutdown::Shutdown;
+
+/// Fetch and store the experience, returns false if the code has no playground
+async fn check_experience(
+    client: &mut PostgresClient,
+    source: &mut dyn PlaygroundSource,
+    rate_limiter: &RateLimiter,
+    code: i32,
+) -> error::Result<bool> {
+    let e_code = ExperienceCode::from_i32(code)?;
+    // don't go to fast, otherwise you will get temporarily blocked.
+    rate_limiter.acquire(client).await;
+    let experience = match source.fetch(&e_code).await {
+        Ok(experience) => experience,
+        Err(e) => {
+            if let Err(ledger_error) = client.record_attempt(code, e.outcome(), Some(e.to_string()))
+            {
+                log::warn!("couldn't update crawl ledger: {}", ledger_error);
+            }
+            if let ExplorerError::Throttled(_) = e {
+                rate_limiter.throttled(client);
+            }
+            return Err(e);
+        }
+    };
+    rate_limiter.succeeded(client);
+
+    let outcome = match experience {
+        Some(_) => CrawlOutcome::Found,
+        None => CrawlOutcome::NotFound,
+    };
+    if let Err(e) = client.record_attempt(code, outcome, None) {
+        log::warn!("couldn't update crawl ledger for {}: {:#}", code, e);
+    }
+
+    match experience {
+        Some(experience) => {
+            println!("{}", experience.playground_name);
+            client.add_or_update_experience(experience)?;
+            Ok(true)
+        }
+        None => {
+            if client.mark_experience_deleted(code)? {
+                log::info!("{} no longer exists, marked as deleted", code);
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Parse and store every recorded response again, without going to EA.
+/// Responses older than what is stored for their experience are skipped
+async fn replay(client: &mut PostgresClient, mut source: ReplaySource) -> anyhow::Result<()> {
+    let (mut found, mut not_found, mut outdated) = (0, 0, 0);
+    while let Some(code) = source.advance()? {
+        let last_seen = client.last_seen(code)?;
+        if last_seen.is_some() && source.recorded_at() < last_seen {
+            outdated += 1;
+            continue;
+        }
+        let e_code = ExperienceCode::from_i32(code)?;
+        match source.fetch(&e_code).await? {
+            Some(experience) => {
+                client.add_or_update_experience(experience)?;
+                found += 1;
+            }
+            None => {
+                client.mark_experience_deleted(code)?;
+                not_found += 1;
+            }
+        }
+    }
+    log::info!(
+        "Replayed {} experiences and {} codes without a playground, skipped {} outdated responses",
+        found,
+        not_found,
+        outdated
+    );
+    Ok(())
+}
+
+fn save_cursor(client: &mut PostgresClient, cursor: i32) {
+    if let Err(e) = client.set_current_experience(cursor) {
+        log::warn!("couldn't save cursor {}: {:#}", cursor, e);
+    }
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     flexi_logger::Logger::try_with_str("info")?.start()?;
     log::info!("Starting...");
 
-    let last_update = Arc::new(atomic::AtomicI64::new(chrono::Utc::now().timestamp() / 60));
-    let last_update_clone = Arc::clone(&last_update);
-
+    let health = Health::new();
+    let hello = health.filter();
     tokio::spawn(async move {
-        let hello = warp::any().map(move || {
-            let last_update_i64 = last_update_clone.load(atomic::Ordering::Relaxed);
-            let now_minutes = chrono::Utc::now().timestamp() / 60;
-
-            // error if 10 minutes without updates
-            if (now_minutes - last_update_i64) > 10 {
-                warp::reply::with_status(
-                    format!("{}", now_minutes - last_update_i64),
-                    warp::http::StatusCode::SERVICE_UNAVAILABLE,
-                )
-            } else {
-                warp::reply::with_status(
-                    format!("{}", now_minutes - last_update_i64),
-                    warp::http::StatusCode::OK,
-                )
-            }
-        });
         warp::serve(hello).run(([0, 0, 0, 0], 3030)).await;
     });
 
-    let mut client = PostgresClient::connect()?;
+    let mut client = PostgresClient::connect(&health)?;
 
-    let mongo_client = MongoClient::connect().await?;
+    // rebuild the experiences from recorded responses instead of crawling
+    if let Ok(dir) = env::var("REPLAY_DIR") {
+        return replay(&mut client, ReplaySource::open(dir)?).await;
+    }
 
-    let mut standalone_client = StandaloneClient {
-        kingston_client: None,
-    };
-    standalone_client.connect(mongo_client).await?;
+    let mongo_client = MongoClient::connect(&health).await?;
+
+    let mut source =
+        playground_source::connect(&health, "standalone", mongo_client, client.clone()).await?;
     let mut current_experience = client.current_experience()?;
+    let mut frontier = Frontier::from_env(current_experience);
+    let mut refresh = RefreshScheduler::from_env();
+    let retry_policy = RetryPolicy::from_env();
+    let rate_limiter = RateLimiter::from_env();
+    let mut auth_backoff = Backoff::new();
+    let mut failed_attempts = 0;
+    let mut shutdown = Shutdown::listen()?;
 
-    loop {
-        let e_code = ExperienceCode::from_i32(current_experience)?;
-
-        let res = standalone_client.get_playground(&e_code).await?;
-
-        if let Some(playground) = res.playground {
-            println!(
-                "{}",
-                playground
-                    .clone()
-                    .original_playground
-                    .unwrap()
-                    .playground_name
-            );
-            client.add_or_update_experience(Experience::init_standalone(e_code, playground)?)
+    while !shutdown.is_requested() {
+        // refresh stored experiences next to the discovery of new ones
+        match refresh.next(&mut client) {
+            Ok(Some(code)) => {
+                log::info!("refreshing {}", code);
+                if let Err(e) =
+                    check_experience(&mut client, source.as_mut(), &rate_limiter, code).await
+                {
+                    log::warn!("refreshing {} failed: {:#}", code, e);
+                }
+                if shutdown.is_requested() {
+                    break;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("couldn't load stale experiences: {:#}", e),
         }
 
-        // don't go to fast, otherwise you will get temporarily blocked.
-        sleep(Duration::from_secs(3)).await;
+        // codes past the last known experience while looking for the frontier
+        let probe = frontier.probe();
+        let code = probe.unwrap_or(current_experience);
 
-        last_update.store(
-            chrono::Utc::now().timestamp() / 60,
-            atomic::Ordering::Relaxed,
-        );
-        current_experience += 1;
-        client.set_current_experience(current_experience);
+        let found = match check_experience(&mut client, source.as_mut(), &rate_limiter, code).await
+        {
+            Ok(found) => {
+                failed_attempts = 0;
+                auth_backoff.reset();
+                Some(found)
+            }
+            // the fleet is backing off, the next token waits for it
+            Err(ExplorerError::Throttled(_)) => continue,
+            Err(e @ ExplorerError::Unauthorized(_)) => {
+                let delay = auth_backoff.advance();
+                log::warn!("session rejected, retrying {} in {:?}: {}", code, delay, e);
+                shutdown.sleep(delay).await;
+                continue;
+            }
+            Err(e) => {
+                let error = e.to_string();
+                failed_attempts += 1;
+                // errors that won't go away go to the dead letters right away
+                let delay = match e.retryable() {
+                    true => retry_policy.delay(failed_attempts),
+                    false => None,
+                };
+                match delay {
+                    Some(delay) => {
+                        log::warn!(
+                            "{} failed {} times, retrying in {:?}: {}",
+                            code,
+                            failed_attempts,
+                            delay,
+                            error
+                        );
+                        shutdown.sleep(delay).await;
+                        continue;
+                    }
+                    None => {
+                        log::error!(
+                            "{} failed {} times, moving it to the dead letters",
+                            code,
+                            failed_attempts
+                        );
+                        if let Err(e) =
+                            client.add_dead_letter(code, failed_attempts as i32, Some(error))
+                        {
+                            log::error!("couldn't store dead letter: {:#}", e);
+                        }
+                        failed_attempts = 0;
+                        // unknown whether it exists, the frontier doesn't hear about it
+                        None
+                    }
+                }
+            }
+        };
+
+        if frontier.is_tailing() {
+            shutdown.sleep(frontier.tail_poll()).await;
+        }
+
+        health.touch();
+        match (probe, found) {
+            (Some(_), Some(found)) => {
+                if let Some(restart) = frontier.observe_probe(code, found) {
+                    current_experience = restart;
+                    save_cursor(&mut client, current_experience);
+                }
+            }
+            (Some(_), None) => frontier.abandon_probe(),
+            (None, found) => {
+                if let Some(found) = found {
+                    frontier.observe(code, found);
+                }
+                current_experience += 1;
+                save_cursor(&mut client, current_experience);
+            }
+        }
     }
+
+    save_cursor(&mut client, current_experience);
+    log::info!(
+        "Stopped, continuing from {} on the next start",
+        current_experience
+    );
+    Ok(())
 }
@@ -0,0 +1,111 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::bail;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{connectors::postgres::models::CrawlOutcome, error::ExplorerError};
+
+/// Bump when the messages change in a way older binaries can't read
+pub const PROTOCOL_VERSION: u32 = 3;
+/// How often workers send a heartbeat
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Next code of the sequential crawl
+    Discover,
+    /// Code checked while looking for the end of the code space
+    Probe,
+    /// Already stored experience that is fetched again
+    Refresh,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Discover => "discover",
+            JobKind::Probe => "probe",
+            JobKind::Refresh => "refresh",
+        }
+    }
+}
+
+/// Sent by the host on experience_code-v1
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkItem {
+    pub code: i32,
+    /// Starts at 1, raised on every retry
+    pub attempt: u32,
+    pub kind: JobKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkResult {
+    pub worker: String,
+    pub code: i32,
+    pub attempt: u32,
+    pub kind: JobKind,
+    pub outcome: CrawlOutcome,
+    pub duration_ms: u64,
+    pub error: Option<ExplorerError>,
+}
+
+/// Sent periodically by every worker so the host knows it's alive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub worker: String,
+    /// Item the worker is busy with
+    pub assignment: Option<WorkItem>,
+    pub processed: u64,
+    pub errors: u64,
+    /// Errors by their label
+    pub errors_by_kind: BTreeMap<String, u64>,
+}
+
+/// Sent by the workers on experience_workerstatuscollector-v1
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WorkerMessage {
+    Startup {
+        worker: String,
+    },
+    Heartbeat(Heartbeat),
+    Result(WorkResult),
+    /// The worker stopped consuming and is about to exit
+    Shutdown {
+        worker: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    version: u32,
+    message: T,
+}
+
+#[derive(Deserialize)]
+struct VersionHeader {
+    version: u32,
+}
+
+pub fn encode<T: Serialize>(message: T) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&Envelope {
+        version: PROTOCOL_VERSION,
+        message,
+    })?)
+}
+
+/// Parse a message, rejecting the ones from another protocol version
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> anyhow::Result<T> {
+    let header: VersionHeader = serde_json::from_slice(data)?;
+    if header.version != PROTOCOL_VERSION {
+        bail!(
+            "unsupported protocol version {}, expected {}",
+            header.version,
+            PROTOCOL_VERSION
+        );
+    }
+    let envelope: Envelope<T> = serde_json::from_slice(data)?;
+    Ok(envelope.message)
+}
@@ -1,128 +1,726 @@
-use std::sync::{
-    atomic::{self, AtomicI64},
-    Arc,
+use std::{
+    collections::{BTreeMap, HashSet},
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use anyhow::Result;
 use chrono::Utc;
 use connectors::postgres::lib::PostgresClient;
 use futures::StreamExt;
-use lapin::Channel;
 use tokio::runtime::Runtime;
 use warp::Filter;
 
+mod clients;
 mod connectors;
+mod dispatch_window;
+mod error;
 mod experience_code;
+mod frontier;
+mod messages;
+mod rate_limit;
+mod refresh;
+mod retry;
+mod shutdown;
+mod worker;
+mod worker_registry;
 
-use crate::connectors::ampq;
+use crate::{
+    connectors::{
+        health::{Backoff, Health},
+        postgres::models::{CrawlOutcome, DispatchedWork},
+        transport::{
+            lib::{Delivery, Transport},
+            memory::MemoryTransport,
+            rabbit::RabbitTransport,
+        },
+    },
+    dispatch_window::DispatchWindow,
+    error::ExplorerError,
+    frontier::Frontier,
+    messages::{JobKind, WorkItem, WorkResult, WorkerMessage, HEARTBEAT_INTERVAL},
+    refresh::RefreshScheduler,
+    retry::RetryPolicy,
+    shutdown::Shutdown,
+    worker::{FunctionWorker, QueueBackend},
+    worker_registry::WorkerRegistry,
+};
+
+/// Amount of codes that can be handed out to workers at the same time
+const DEFAULT_WINDOW_SIZE: usize = 10;
+/// Workers started next to the host with QUEUE_BACKEND=memory
+const DEFAULT_MEMORY_WORKERS: usize = 1;
+/// How often the refresh budget is spent on stale experiences
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// Workers without a heartbeat for this long are considered gone
+const WORKER_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time for all workers to report their assignment before lost work is sent again
+const RECONCILE_DELAY: Duration = Duration::from_secs(HEARTBEAT_INTERVAL.as_secs() * 2);
+const QUES: [&str; 2] = ["experience_code-v1", "experience_workerstatuscollector-v1"];
+
+/// Name of the queue that delays a code for `delay` before it's sent to the workers again
+fn retry_que(delay: Duration) -> String {
+    format!("experience_code_retry-v1.{}s", delay.as_secs())
+}
 
 pub(crate) struct FunctionMaster {
     pub client: PostgresClient,
-    pub rabbit: Channel,
-    // last group checkup
-    pub last_update: Arc<AtomicI64>,
+    /// Queue transport, RabbitMQ unless created with `with_transport`
+    pub transport: Arc<dyn Transport>,
+    /// Healthcheck state, touched on every status message
+    pub health: Health,
+    /// Max amount of codes in flight, set with DISPATCH_WINDOW
+    pub window_size: usize,
+    pub retry_policy: RetryPolicy,
+    window: DispatchWindow,
+    frontier: Frontier,
+    /// Code sent to find the end of the code space, outside of the window
+    probe: Option<i32>,
+    refresh: RefreshScheduler,
+    /// Refreshes sent and not answered yet, they share the work queue with the crawl
+    refreshes: HashSet<i32>,
+    /// Workers that sent a heartbeat, shared with the http server
+    workers: Arc<Mutex<WorkerRegistry>>,
 }
 
 impl FunctionMaster {
-    pub async fn new(last_update: Arc<AtomicI64>) -> Result<Self> {
+    pub async fn new(health: Health, workers: Arc<Mutex<WorkerRegistry>>) -> Result<Self> {
+        let transport = Arc::new(RabbitTransport::connect(&health).await?);
+        Self::with_transport(transport, health, workers)
+    }
+
+    /// Create a host on any queue transport, like the in-memory one
+    pub fn with_transport(
+        transport: Arc<dyn Transport>,
+        health: Health,
+        workers: Arc<Mutex<WorkerRegistry>>,
+    ) -> Result<Self> {
+        let mut client = PostgresClient::connect(&health)?;
+        let cursor = client.current_experience()?;
+        let window_size = env::var("DISPATCH_WINDOW")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_WINDOW_SIZE);
+
         Ok(Self {
-            client: PostgresClient::connect()?,
-            rabbit: ampq::create_channel().await?,
-            last_update,
+            client,
+            transport,
+            health,
+            window_size,
+            retry_policy: RetryPolicy::from_env(),
+            window: DispatchWindow::new(cursor, window_size),
+            frontier: Frontier::from_env(cursor),
+            probe: None,
+            refresh: RefreshScheduler::from_env(),
+            refreshes: HashSet::new(),
+            workers,
         })
     }
 
-    /// Init all needed ques
+    /// Hand out sequential codes, or a single probe while looking for the frontier.
+    /// Probes in tail mode wait for `poll_tail`
+    async fn dispatch(&mut self) {
+        match self.frontier.probe() {
+            None => {
+                for code in self.window.fill() {
+                    // before publishing, a fast worker could record its result first
+                    if let Err(e) = self.client.mark_dispatched(code) {
+                        log::warn!("couldn't update crawl ledger for {}: {:#}", code, e);
+                    }
+                    self.publish_work(
+                        "experience_code-v1",
+                        WorkItem {
+                            code,
+                            attempt: 1,
+                            kind: JobKind::Discover,
+                        },
+                    )
+                    .await;
+                }
+            }
+            Some(_) if self.frontier.is_tailing() => {}
+            Some(_) => self.publish_probe().await,
+        }
+    }
+
+    /// Check the next code past the frontier, unless a probe is still out
+    async fn publish_probe(&mut self) {
+        let probe = match self.frontier.probe() {
+            Some(probe) if self.probe.is_none() => probe,
+            _ => return,
+        };
+        self.probe = Some(probe);
+        self.publish_work(
+            "experience_code-v1",
+            WorkItem {
+                code: probe,
+                attempt: 1,
+                kind: JobKind::Probe,
+            },
+        )
+        .await;
+    }
+
+    /// Poll for new experiences just past the frontier, on the FRONTIER_TAIL_POLL_SECS timer
+    async fn poll_tail(&mut self) {
+        if self.frontier.is_tailing() {
+            self.publish_probe().await;
+        }
+    }
+
+    /// Persist the cursor, it's saved again with the in-flight work when this fails
+    fn save_cursor(&mut self, cursor: i32) {
+        if let Err(e) = self.client.set_current_experience(cursor) {
+            log::warn!("couldn't save cursor {}: {:#}", cursor, e);
+        }
+    }
+
+    /// Complete a sequential code and let the frontier know about the ones in order,
+    /// dead-lettered codes (`found` is None) don't count as found or missing
+    fn complete(&mut self, code: i32, found: Option<bool>) {
+        let advanced = self.window.complete(code, found);
+        if !advanced.is_empty() {
+            self.save_cursor(self.window.cursor());
+        }
+        for (code, found) in advanced {
+            if let Some(found) = found {
+                self.frontier.observe(code, found);
+            }
+        }
+    }
+
+    async fn handle_result(&mut self, result: WorkResult) {
+        log::info!(
+            "Finished {} ({:?}, attempt {}) on {} in {}ms: {:?}",
+            result.code,
+            result.kind,
+            result.attempt,
+            result.worker,
+            result.duration_ms,
+            result.outcome
+        );
+        let found = result.outcome == CrawlOutcome::Found;
+
+        match result.kind {
+            // refreshes run next to the crawl and don't move it
+            JobKind::Refresh => {
+                self.refreshes.remove(&result.code);
+                return;
+            }
+            JobKind::Probe => {
+                if self.probe != Some(result.code) {
+                    return;
+                }
+                let que = match &result.error {
+                    None | Some(ExplorerError::NotFound) => None,
+                    // the next poll checks it again
+                    Some(_) if self.frontier.is_tailing() => {
+                        self.probe = None;
+                        return;
+                    }
+                    Some(ExplorerError::Throttled(_)) | Some(ExplorerError::Unauthorized(_)) => {
+                        Some(self.backoff_que(result.attempt))
+                    }
+                    Some(error) if error.retryable() => {
+                        self.retry_policy.delay(result.attempt).map(retry_que)
+                    }
+                    Some(_) => None,
+                };
+                if let Some(que) = que {
+                    self.publish_work(
+                        &que,
+                        WorkItem {
+                            code: result.code,
+                            attempt: result.attempt + 1,
+                            kind: JobKind::Probe,
+                        },
+                    )
+                    .await;
+                    return;
+                }
+
+                self.probe = None;
+                if let Some(error) = result.error.filter(|e| *e != ExplorerError::NotFound) {
+                    log::warn!("giving up on probe {}: {}", result.code, error);
+                    self.frontier.abandon_probe();
+                } else if let Some(restart) = self.frontier.observe_probe(result.code, found) {
+                    self.window = DispatchWindow::new(restart, self.window_size);
+                    self.save_cursor(restart);
+                }
+            }
+            JobKind::Discover => {
+                if !self.window.is_in_flight(result.code) {
+                    return;
+                }
+                match result.error {
+                    None | Some(ExplorerError::NotFound) => {
+                        self.complete(result.code, Some(found));
+                    }
+                    // not the code's fault, it stays in the window and is never dead-lettered
+                    Some(ExplorerError::Throttled(_)) | Some(ExplorerError::Unauthorized(_)) => {
+                        let failures = self.window.fail(result.code);
+                        let que = self.backoff_que(failures);
+                        self.publish_work(
+                            &que,
+                            WorkItem {
+                                code: result.code,
+                                attempt: failures + 1,
+                                kind: JobKind::Discover,
+                            },
+                        )
+                        .await;
+                        return;
+                    }
+                    Some(error) => {
+                        let failures = self.window.fail(result.code);
+                        // errors that won't go away go to the dead letters right away
+                        let delay = match error.retryable() {
+                            true => self.retry_policy.delay(failures),
+                            false => None,
+                        };
+                        match delay {
+                            Some(delay) => {
+                                log::warn!(
+                                    "{} failed {} times, retrying in {:?}: {}",
+                                    result.code,
+                                    failures,
+                                    delay,
+                                    error
+                                );
+                                self.publish_work(
+                                    &retry_que(delay),
+                                    WorkItem {
+                                        code: result.code,
+                                        attempt: failures + 1,
+                                        kind: JobKind::Discover,
+                                    },
+                                )
+                                .await;
+                                return;
+                            }
+                            None => {
+                                self.dead_letter(result.code, failures, Some(error.to_string()));
+                                self.complete(result.code, None);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        self.dispatch().await;
+    }
+
+    /// Queue that holds throttled or rejected work for the backoff after `failures`
+    fn backoff_que(&self, failures: u32) -> String {
+        match self.retry_policy.delays().is_empty() {
+            true => "experience_code-v1".to_string(),
+            false => retry_que(self.retry_policy.backoff(failures)),
+        }
+    }
+
+    async fn publish_work(&mut self, que: &str, item: WorkItem) {
+        let code = item.code;
+        let message = match messages::encode(item) {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!("couldn't encode work item for {}: {:#}", code, e);
+                return;
+            }
+        };
+        match self.transport.publish(que, message).await {
+            Ok(_) => {}
+            Err(_) => log::error!("couldn't make queue for {}", &code),
+        };
+    }
+
+    /// Stop retrying a code and store it with the last error
+    fn dead_letter(&mut self, code: i32, failures: u32, error: Option<String>) {
+        log::error!(
+            "{} failed {} times, moving it to the dead letters",
+            code,
+            failures
+        );
+        if let Err(e) = self.client.add_dead_letter(code, failures as i32, error) {
+            log::error!("couldn't store dead letter for {}: {:#}", code, e);
+        }
+    }
+
+    /// Init all needed ques, existing ones keep their messages
     async fn init_ques(&self) -> Result<()> {
-        let ques = ["experience_code-v1", "experience_workerstatuscollector-v1"];
+        for que in QUES.iter() {
+            self.transport.declare_que(que).await?;
+        }
 
-        for que in ques.iter() {
-            ampq::delete_que(&self.rabbit, que).await?;
-            ampq::declare_que(&self.rabbit, que).await?;
+        for delay in self.retry_policy.delays() {
+            self.transport
+                .declare_retry_que(&retry_que(delay), "experience_code-v1", delay)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Drop all queued work and the saved in-flight set, the crawl restarts at the cursor
+    async fn purge_ques(&mut self) -> Result<()> {
+        for que in QUES.iter() {
+            self.transport.delete_que(que).await?;
+        }
+        for delay in self.retry_policy.delays() {
+            self.transport.delete_que(&retry_que(delay)).await?;
+        }
+        Ok(self.client.save_dispatched_work(vec![])?)
+    }
+
+    /// Rebuild the window from the work saved on the last stop and the crawl ledger
+    fn restore(&mut self) -> Result<()> {
+        let cursor = self.client.current_experience()?;
+        self.window = DispatchWindow::new(cursor, self.window_size);
+        self.frontier = Frontier::from_env(cursor);
+        self.probe = None;
+
+        // probes aren't restored, the frontier starts over from the cursor
+        let dispatched = self.client.dispatched_work()?;
+        self.refreshes = dispatched
+            .iter()
+            .filter(|work| work.kind == JobKind::Refresh.as_str())
+            .map(|work| work.code)
+            .collect();
+        let saved: BTreeMap<i32, i32> = dispatched
+            .into_iter()
+            .filter(|work| work.kind == JobKind::Discover.as_str() && work.code >= cursor)
+            .map(|work| (work.code, work.attempt))
+            .collect();
+        let last = match saved.keys().last() {
+            Some(last) => *last,
+            None => return Ok(()),
+        };
+
+        // everything between the cursor and the last saved code was handed out,
+        // the ledger knows which of those finished while the host was gone
+        for code in cursor..=last {
+            let outcome = self
+                .client
+                .get_crawl_attempt(code)?
+                .map(|attempt| attempt.outcome);
+            let failures = saved.get(&code).map(|attempt| (attempt - 1).max(0) as u32);
+            self.window.resume(code, failures.unwrap_or_default());
+            match outcome.as_deref() {
+                Some("found") => self.complete(code, Some(true)),
+                Some("not_found") => self.complete(code, Some(false)),
+                // dead lettered before the host stopped
+                Some("error") if failures.is_none() => self.complete(code, None),
+                _ => {}
+            }
         }
 
+        log::info!(
+            "Restored cursor {} with {} codes in flight",
+            self.window.cursor(),
+            self.window.in_flight().len()
+        );
+        Ok(())
+    }
+
+    /// Send work in flight again when nobody has it anymore, like after a broker restart.
+    /// Only counts are known, so as many codes as are short are sent again
+    async fn reconcile(&mut self) -> Result<()> {
+        let mut queued = self.transport.que_depth("experience_code-v1").await?;
+        for delay in self.retry_policy.delays() {
+            queued += self.transport.que_depth(&retry_que(delay)).await?;
+        }
+
+        let held: HashSet<i32> = self
+            .workers
+            .lock()
+            .unwrap()
+            .workers()
+            .into_iter()
+            .filter_map(|info| info.assignment.map(|item| item.code))
+            .collect();
+        let mut missing: Vec<WorkItem> = self
+            .window
+            .in_flight()
+            .into_iter()
+            .filter(|(code, _)| !held.contains(code))
+            .map(|(code, failures)| WorkItem {
+                code,
+                attempt: failures + 1,
+                kind: JobKind::Discover,
+            })
+            .collect();
+        if let Some(code) = self.probe.filter(|code| !held.contains(code)) {
+            missing.push(WorkItem {
+                code,
+                attempt: 1,
+                kind: JobKind::Probe,
+            });
+        }
+
+        // refreshes wait in the same queues, they aren't sent again
+        let refreshes = self
+            .refreshes
+            .iter()
+            .filter(|code| !held.contains(code))
+            .count();
+        let queued = (queued as usize).saturating_sub(refreshes);
+        let short = missing.len().saturating_sub(queued);
+        if short == 0 {
+            log::info!("{} codes queued, nothing was lost", queued);
+            return Ok(());
+        }
+        log::warn!(
+            "{} codes waiting for a worker but only {} queued, sending {} again",
+            missing.len(),
+            queued,
+            short
+        );
+        // the lowest codes first, they hold back the cursor
+        for item in missing.into_iter().take(short) {
+            self.publish_work("experience_code-v1", item).await;
+        }
         Ok(())
     }
 
     /// Run Fortress node
     pub async fn run(&mut self) -> Result<()> {
-        loop {
-            log::info!("Started!");
+        let mut shutdown = Shutdown::listen()?;
+
+        if matches!(env::var("PURGE_QUEUES").as_deref(), Ok("1") | Ok("true")) {
+            log::warn!("PURGE_QUEUES is set, dropping all queued work");
+            self.purge_ques().await?;
+        }
+        let mut backoff = Backoff::new();
+        while let Err(e) = self.restore() {
+            let delay = backoff.advance();
+            log::warn!(
+                "couldn't restore the crawl, retrying in {:?}: {:#}",
+                delay,
+                e
+            );
+            // nothing was restored, there is nothing to save either
+            if !shutdown.sleep(delay).await {
+                return Ok(());
+            }
+        }
+        backoff.reset();
 
-            self.init_ques().await?;
+        while !shutdown.is_requested() {
+            log::info!("Started!");
 
-            self.run_loop().await?;
+            let result = match self.init_ques().await {
+                Ok(_) => self.run_loop(&mut shutdown).await,
+                Err(e) => Err(e),
+            };
+            // the broker is down, stay up with a degraded health until it's back
+            match result {
+                Ok(_) => backoff.reset(),
+                Err(e) => {
+                    self.health.degrade("rabbitmq", &e);
+                    let delay = backoff.advance();
+                    log::warn!("queues failed, retrying in {:?}: {:#}", delay, e);
+                    shutdown.sleep(delay).await;
+                }
+            }
         }
+
+        self.save_state()?;
+        log::info!(
+            "Saved cursor {} and {} codes in flight",
+            self.window.cursor(),
+            self.window.in_flight().len()
+        );
+        self.transport.close().await?;
+        Ok(())
     }
 
-    async fn run_loop(&mut self) -> Result<()> {
-        let mut current_experience = self.client.current_experience()?;
-        match ampq::publish(
-            &self.rabbit,
-            "experience_code-v1",
-            current_experience.to_string(),
-        )
-        .await
-        {
-            Ok(_) => {}
-            Err(_) => log::error!("couldn't make queue for {}", &current_experience),
-        };
+    /// Persist the cursor and the work that is still in flight
+    fn save_state(&mut self) -> Result<()> {
+        let cursor = self.window.cursor();
+        self.client.set_current_experience(cursor)?;
 
-        log::info!("Sent initial item");
+        let saved_at = Utc::now().naive_utc();
+        let mut work: Vec<DispatchedWork> = self
+            .window
+            .in_flight()
+            .into_iter()
+            .map(|(code, failures)| DispatchedWork {
+                code,
+                kind: JobKind::Discover.as_str().to_string(),
+                attempt: failures as i32 + 1,
+                saved_at,
+            })
+            .collect();
+        if let Some(code) = self.probe.filter(|code| !self.window.is_in_flight(*code)) {
+            work.push(DispatchedWork {
+                code,
+                kind: JobKind::Probe.as_str().to_string(),
+                attempt: 1,
+                saved_at,
+            });
+        }
+        // counted by reconcile after a restart
+        for code in self.refreshes.iter().copied() {
+            if !self.window.is_in_flight(code) && self.probe != Some(code) {
+                work.push(DispatchedWork {
+                    code,
+                    kind: JobKind::Refresh.as_str().to_string(),
+                    attempt: 1,
+                    saved_at,
+                });
+            }
+        }
 
-        ampq::set_qos(&self.rabbit).await?;
+        Ok(self.client.save_dispatched_work(work)?)
+    }
 
-        let consumer =
-            ampq::new_consumer(&self.rabbit, "experience_workerstatuscollector-v1").await?;
-        let mut iter = consumer;
+    async fn handle_delivery(&mut self, delivery: Delivery) -> Result<()> {
+        // Ack delivery
+        delivery.ack().await?;
 
-        while let Some(next_task) = iter.next().await {
-            // Get delivery
-            let delivery = next_task?;
+        match messages::decode::<WorkerMessage>(&delivery.data) {
+            Ok(WorkerMessage::Startup { worker }) => {
+                log::info!("Worker {} started", worker);
+                self.workers.lock().unwrap().seen(&worker);
+            }
+            Ok(WorkerMessage::Heartbeat(heartbeat)) => {
+                self.workers.lock().unwrap().heartbeat(heartbeat);
+            }
+            Ok(WorkerMessage::Result(result)) => {
+                self.workers
+                    .lock()
+                    .unwrap()
+                    .finished(&result.worker, result.code);
+                self.handle_result(result).await;
+            }
+            Ok(WorkerMessage::Shutdown { worker }) => {
+                log::info!("Worker {} shut down", worker);
+                let assignment = self.workers.lock().unwrap().left(&worker);
+                if let Some(item) = assignment {
+                    self.reissue(&worker, item).await;
+                }
+            }
+            Err(e) => log::warn!("ignoring malformed status message: {:#}", e),
+        }
 
-            let delivery_str = String::from_utf8_lossy(&delivery.data).to_string();
-            let result: &Vec<&str> = &delivery_str.split(';').collect::<Vec<&str>>();
-            let experience_id = &result[0].to_string();
+        // healthcheck
+        self.health.touch();
+        Ok(())
+    }
 
-            // result[1]
+    /// Re-issue the assignments of workers that stopped sending heartbeats
+    async fn reclaim_orphans(&mut self) {
+        let orphaned = self.workers.lock().unwrap().reap();
+        for (worker, item) in orphaned {
+            self.reissue(&worker, item).await;
+        }
+    }
 
-            // Ack delivery
-            delivery
-                .ack(lapin::options::BasicAckOptions::default())
-                .await?;
-            log::info!("Finished {} status {}", experience_id, result[1]);
+    /// Send the assignment of a worker that is gone to another one, if it's still needed
+    async fn reissue(&mut self, worker: &str, item: WorkItem) {
+        let tracked = match item.kind {
+            JobKind::Discover => self.window.is_in_flight(item.code),
+            JobKind::Probe => self.probe == Some(item.code),
+            JobKind::Refresh => true,
+        };
+        if tracked {
+            // the worker is gone, that's not a failed attempt of the code
+            log::warn!("re-issuing {} held by worker {}", item.code, worker);
+            self.publish_work("experience_code-v1", item).await;
+        }
+    }
 
-            if result[1] != "error" {
-                current_experience += 1;
-                self.client
-                    .set_current_experience(current_experience.clone());
+    /// Queue stale experiences for a refresh, as far as the budget allows
+    async fn schedule_refreshes(&mut self) {
+        loop {
+            match self.refresh.next(&mut self.client) {
+                Ok(Some(code)) => {
+                    log::info!("refreshing {}", code);
+                    self.refreshes.insert(code);
+                    self.publish_work(
+                        "experience_code-v1",
+                        WorkItem {
+                            code,
+                            attempt: 1,
+                            kind: JobKind::Refresh,
+                        },
+                    )
+                    .await;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("couldn't load stale experiences: {:#}", e);
+                    break;
+                }
             }
-            match ampq::publish(
-                &self.rabbit,
-                "experience_code-v1",
-                current_experience.to_string(),
-            )
-            .await
-            {
-                Ok(_) => {}
-                Err(_) => log::error!("couldn't make queue for {}", &current_experience),
-            };
+        }
+    }
+
+    async fn run_loop(&mut self, shutdown: &mut Shutdown) -> Result<()> {
+        self.dispatch().await;
+
+        log::info!("Sent initial items");
 
-            // healthcheck
-            let current_timestamp_minutes =
-                Utc::now().timestamp().checked_div(60).unwrap_or_default();
-            self.last_update
-                .store(current_timestamp_minutes, atomic::Ordering::Relaxed);
+        self.transport.set_qos(1).await?;
+
+        let consumer = self
+            .transport
+            .consume("experience_workerstatuscollector-v1")
+            .await?;
+        let mut iter = consumer;
+        self.health.recover("rabbitmq");
+
+        let mut refresh_tick = tokio::time::interval(REFRESH_INTERVAL);
+        let mut reap_tick = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let mut tail_tick =
+            tokio::time::interval(self.frontier.tail_poll().max(Duration::from_secs(1)));
+        let reconcile = tokio::time::sleep(RECONCILE_DELAY);
+        tokio::pin!(reconcile);
+        let mut reconciled = false;
+
+        loop {
+            tokio::select! {
+                next_task = iter.next() => match next_task {
+                    Some(Ok(delivery)) => {
+                        if let Err(e) = self.handle_delivery(delivery).await {
+                            log::warn!("couldn't handle status message: {:#}", e);
+                            break;
+                        }
+                    }
+                    Some(Err(e)) => {
+                        log::warn!("status consumer failed: {:#}", e);
+                        break;
+                    }
+                    // connection dropped, run starts over on a new channel
+                    None => break,
+                },
+                _ = refresh_tick.tick() => self.schedule_refreshes().await,
+                _ = tail_tick.tick() => self.poll_tail().await,
+                _ = reap_tick.tick() => {
+                    self.reclaim_orphans().await;
+                    if let Err(e) = self.save_state() {
+                        log::warn!("couldn't save the in-flight work: {:#}", e);
+                    }
+                }
+                _ = &mut reconcile, if !reconciled => {
+                    reconciled = true;
+                    if let Err(e) = self.reconcile().await {
+                        log::warn!("couldn't reconcile the queues: {:#}", e);
+                    }
+                }
+                _ = shutdown.requested() => break,
+            }
         }
 
-        self.rabbit.close(200, "Normal shutdown").await?;
         Ok(())
     }
 }
 
 fn main() -> anyhow::Result<()> {
-    let last_update = Arc::new(atomic::AtomicI64::new(0));
-    let last_update_clone = Arc::clone(&last_update);
+    let health = Health::new();
+    let workers = Arc::new(Mutex::new(WorkerRegistry::new(WORKER_TIMEOUT)));
+    let workers_clone = Arc::clone(&workers);
 
     flexi_logger::Logger::try_with_str("info")
         .unwrap()
@@ -137,32 +735,238 @@ fn main() -> anyhow::Result<()> {
     let rt = Runtime::new().unwrap();
 
     // healthcheck
+    let hello = health.filter();
     rt.spawn(async move {
-        let hello = warp::any().map(move || {
-            let last_update_i64 = last_update_clone.load(atomic::Ordering::Relaxed);
-            let now_minutes = Utc::now().timestamp().checked_div(60).unwrap_or_default();
-
-            // error if 10 minutes without traffic
-            if (now_minutes - last_update_i64) > 10 {
-                warp::reply::with_status(
-                    format!("{}", now_minutes - last_update_i64),
-                    warp::http::StatusCode::SERVICE_UNAVAILABLE,
-                )
-            } else {
-                warp::reply::with_status(
-                    format!("{}", now_minutes - last_update_i64),
-                    warp::http::StatusCode::OK,
-                )
-            }
-        });
-        warp::serve(hello).run(([0, 0, 0, 0], 3030)).await;
+        // fleet overview
+        let workers = warp::path("workers")
+            .map(move || warp::reply::json(&workers_clone.lock().unwrap().workers()));
+        warp::serve(workers.or(hello))
+            .run(([0, 0, 0, 0], 3030))
+            .await;
     });
 
     // For multigame we can potentially pass game param in here
-    let mut fortress = rt.block_on(FunctionMaster::new(last_update))?;
+    let mut fortress = match QueueBackend::from_env()? {
+        // host and workers in a single process, without a broker
+        QueueBackend::Memory => {
+            let transport = Arc::new(MemoryTransport::new());
+            let count = env::var("MEMORY_WORKERS")
+                .ok()
+                .and_then(|count| count.parse().ok())
+                .unwrap_or(DEFAULT_MEMORY_WORKERS);
+            for _ in 0..count {
+                let mut worker = rt.block_on(FunctionWorker::with_transport(
+                    Some(transport.clone()),
+                    health.clone(),
+                ))?;
+                rt.spawn(async move {
+                    if let Err(e) = worker.run().await {
+                        log::error!("worker {} stopped: {:#}", worker.uuid, e);
+                    }
+                });
+            }
+            log::info!("Running with {} workers on in-memory queues", count);
+            FunctionMaster::with_transport(transport, health, workers)?
+        }
+        _ => rt.block_on(FunctionMaster::new(health, workers))?,
+    };
 
     // Run infinity loop
     rt.block_on(fortress.run())?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connectors::transport::lib::DeliveryStream;
+
+    fn host(transport: Arc<dyn Transport>, cursor: i32) -> FunctionMaster {
+        let health = Health::new();
+        FunctionMaster {
+            client: PostgresClient::unreachable(&health),
+            transport,
+            health,
+            window_size: 3,
+            retry_policy: RetryPolicy::from_env(),
+            window: DispatchWindow::new(cursor, 3),
+            frontier: Frontier::from_env(cursor),
+            probe: None,
+            refresh: RefreshScheduler::from_env(),
+            refreshes: HashSet::new(),
+            workers: Arc::new(Mutex::new(WorkerRegistry::new(WORKER_TIMEOUT))),
+        }
+    }
+
+    async fn next_item(work: &mut DeliveryStream) -> WorkItem {
+        let delivery = tokio::time::timeout(Duration::from_secs(1), work.next())
+            .await
+            .expect("no work item in time")
+            .unwrap()
+            .unwrap();
+        delivery.ack().await.unwrap();
+        messages::decode(&delivery.data).unwrap()
+    }
+
+    /// Report `item` back the way a worker does
+    async fn report(transport: &MemoryTransport, item: &WorkItem, outcome: CrawlOutcome) {
+        let error = match outcome {
+            CrawlOutcome::NotFound => Some(ExplorerError::NotFound),
+            CrawlOutcome::Throttled => Some(ExplorerError::Throttled("slow down".to_string())),
+            CrawlOutcome::Error => Some(ExplorerError::Transport("unavailable".to_string())),
+            _ => None,
+        };
+        let result = WorkResult {
+            worker: "test".to_string(),
+            code: item.code,
+            attempt: item.attempt,
+            kind: item.kind,
+            outcome,
+            duration_ms: 0,
+            error,
+        };
+        transport
+            .publish(
+                QUES[1],
+                messages::encode(WorkerMessage::Result(result)).unwrap(),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn results_move_the_window_over_memory_queues() {
+        let transport = MemoryTransport::new();
+        let mut host = host(Arc::new(transport.clone()), 100);
+        host.init_ques().await.unwrap();
+        let mut work = transport.consume(QUES[0]).await.unwrap();
+        let mut status = transport.consume(QUES[1]).await.unwrap();
+
+        host.dispatch().await;
+        let mut items = vec![];
+        for _ in 0..3 {
+            items.push(next_item(&mut work).await);
+        }
+        let codes: Vec<i32> = items.iter().map(|item| item.code).collect();
+        assert_eq!(codes, [100, 101, 102]);
+
+        // out of order, the cursor waits for 100 but the window is filled again
+        report(&transport, &items[1], CrawlOutcome::Found).await;
+        host.handle_delivery(status.next().await.unwrap().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(host.window.cursor(), 100);
+        assert_eq!(next_item(&mut work).await.code, 103);
+
+        report(&transport, &items[0], CrawlOutcome::NotFound).await;
+        host.handle_delivery(status.next().await.unwrap().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(host.window.cursor(), 102);
+        let item = next_item(&mut work).await;
+        assert_eq!((item.code, item.attempt), (104, 1));
+        assert_eq!(transport.que_depth(QUES[0]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn throttled_codes_stay_in_the_window() {
+        let transport = MemoryTransport::new();
+        let mut host = host(Arc::new(transport.clone()), 100);
+        host.retry_policy = RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(10),
+        };
+        host.init_ques().await.unwrap();
+        let mut work = transport.consume(QUES[0]).await.unwrap();
+        let mut status = transport.consume(QUES[1]).await.unwrap();
+
+        host.dispatch().await;
+        let mut item = next_item(&mut work).await;
+        for _ in 0..2 {
+            next_item(&mut work).await;
+        }
+
+        // past max_attempts, but throttling isn't the code's fault
+        for attempt in 2..=4 {
+            report(&transport, &item, CrawlOutcome::Throttled).await;
+            host.handle_delivery(status.next().await.unwrap().unwrap())
+                .await
+                .unwrap();
+            item = next_item(&mut work).await;
+            assert_eq!((item.code, item.attempt), (100, attempt));
+            assert!(host.window.is_in_flight(100));
+        }
+        assert_eq!(host.window.cursor(), 100);
+    }
+
+    #[tokio::test]
+    async fn failing_probes_are_retried_then_given_up() {
+        let transport = MemoryTransport::new();
+        let mut host = host(Arc::new(transport.clone()), 100);
+        host.retry_policy = RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(10),
+        };
+        host.init_ques().await.unwrap();
+        let mut work = transport.consume(QUES[0]).await.unwrap();
+        let mut status = transport.consume(QUES[1]).await.unwrap();
+
+        let mut code = 100;
+        while host.frontier.probe().is_none() {
+            host.frontier.observe(code, false);
+            code += 1;
+        }
+        host.dispatch().await;
+        let probe = next_item(&mut work).await;
+        assert_eq!(probe.kind, JobKind::Probe);
+
+        report(&transport, &probe, CrawlOutcome::Error).await;
+        host.handle_delivery(status.next().await.unwrap().unwrap())
+            .await
+            .unwrap();
+        let retry = next_item(&mut work).await;
+        assert_eq!((retry.code, retry.attempt), (probe.code, 2));
+
+        // out of attempts, the code isn't taken as missing and the crawl carries on
+        report(&transport, &retry, CrawlOutcome::Error).await;
+        host.handle_delivery(status.next().await.unwrap().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(host.probe, None);
+        assert_eq!(host.frontier.probe(), None);
+        assert_eq!(next_item(&mut work).await.kind, JobKind::Discover);
+    }
+
+    #[tokio::test]
+    async fn reconcile_sends_only_the_lost_codes_again() {
+        let transport = MemoryTransport::new();
+        let mut host = host(Arc::new(transport.clone()), 100);
+        host.init_ques().await.unwrap();
+        let mut work = transport.consume(QUES[0]).await.unwrap();
+
+        host.dispatch().await;
+        host.refreshes.insert(7);
+        host.publish_work(
+            QUES[0],
+            WorkItem {
+                code: 7,
+                attempt: 1,
+                kind: JobKind::Refresh,
+            },
+        )
+        .await;
+        // taken by nobody the host knows about
+        for _ in 0..2 {
+            next_item(&mut work).await;
+        }
+
+        host.reconcile().await.unwrap();
+        let mut codes = vec![];
+        for _ in 0..4 {
+            codes.push(next_item(&mut work).await.code);
+        }
+        assert_eq!(codes, [102, 7, 100, 101]);
+        assert_eq!(transport.que_depth(QUES[0]).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,124 @@
+use std::{env, time::Duration};
+
+use rand::Rng;
+use tokio::time::sleep;
+
+use crate::connectors::postgres::lib::PostgresClient;
+
+/// Requests per minute to Kingston for the whole fleet, the old pace of a single standalone crawler
+const DEFAULT_REQUESTS_PER_MINUTE: f64 = 20.0;
+const DEFAULT_BURST: f64 = 1.0;
+/// Bucket in the rate_limits table
+const BUCKET: &str = "kingston";
+/// Requests per minute of a single account, on top of the fleet budget
+const DEFAULT_ACCOUNT_REQUESTS_PER_MINUTE: f64 = 20.0;
+/// Backoff after the first throttle, doubled for every throttle in a row
+const THROTTLE_BASE_DELAY: Duration = Duration::from_secs(30);
+const THROTTLE_MAX_DELAY: Duration = Duration::from_secs(15 * 60);
+/// Lowest share of the configured rate a throttled fleet slows down to
+const MIN_RATE_FACTOR: f64 = 0.1;
+/// Share of the rate won back with every successful request
+const RECOVERY_STEP: f64 = 0.05;
+
+/// Stretch a wait by up to half, so the processes waiting on the same bucket
+/// don't all come back at once
+fn jitter(wait: Duration) -> Duration {
+    wait.mul_f64(rand::thread_rng().gen_range(1.0..1.5))
+}
+
+/// Request budget shared by every fetcher through Postgres, no matter how many workers run
+pub struct RateLimiter {
+    bucket: String,
+    per_minute: f64,
+    burst: f64,
+}
+
+impl RateLimiter {
+    /// Read KINGSTON_REQUESTS_PER_MINUTE and KINGSTON_BURST, with defaults
+    pub fn from_env() -> Self {
+        let per_minute: f64 = env::var("KINGSTON_REQUESTS_PER_MINUTE")
+            .ok()
+            .and_then(|rate| rate.parse().ok())
+            .unwrap_or(DEFAULT_REQUESTS_PER_MINUTE);
+        let burst: f64 = env::var("KINGSTON_BURST")
+            .ok()
+            .and_then(|burst| burst.parse().ok())
+            .unwrap_or(DEFAULT_BURST);
+        Self {
+            bucket: BUCKET.to_string(),
+            per_minute: per_minute.max(0.1),
+            burst: burst.max(1.0),
+        }
+    }
+
+    /// Budget of a single account, read KINGSTON_ACCOUNT_REQUESTS_PER_MINUTE with a default
+    pub fn for_account(account: &str) -> Self {
+        let per_minute: f64 = env::var("KINGSTON_ACCOUNT_REQUESTS_PER_MINUTE")
+            .ok()
+            .and_then(|rate| rate.parse().ok())
+            .unwrap_or(DEFAULT_ACCOUNT_REQUESTS_PER_MINUTE);
+        Self {
+            bucket: format!("{}:{}", BUCKET, account),
+            per_minute: per_minute.max(0.1),
+            burst: 1.0,
+        }
+    }
+
+    /// Time between requests of a single process at the full rate
+    fn interval(&self) -> Duration {
+        Duration::from_secs_f64(60.0 / self.per_minute)
+    }
+
+    /// Kingston throttled a request, pause the whole fleet with an exponential backoff
+    /// and continue at a lower rate afterwards. Every process adds its own jitter to the wait
+    pub fn throttled(&self, client: &mut PostgresClient) {
+        let delay = |level: i32| {
+            THROTTLE_BASE_DELAY
+                .saturating_mul(2u32.saturating_pow(level.max(0) as u32))
+                .min(THROTTLE_MAX_DELAY)
+        };
+        match client.throttle_bucket(&self.bucket, self.burst, MIN_RATE_FACTOR, delay) {
+            Ok(delay) => log::warn!(
+                "throttled by kingston, backing off {} for {:?}",
+                self.bucket,
+                delay
+            ),
+            Err(e) => log::warn!("couldn't back off {}: {:#}", self.bucket, e),
+        }
+    }
+
+    /// A request went through, slowly go back to the configured rate after throttling
+    pub fn succeeded(&self, client: &mut PostgresClient) {
+        if let Err(e) = client.recover_bucket(&self.bucket, RECOVERY_STEP) {
+            log::warn!("couldn't update the rate limit: {:#}", e);
+        }
+    }
+
+    /// Wait until the fleet may send another request.
+    /// Without a database it paces this process alone at the full rate
+    pub async fn acquire(&self, client: &mut PostgresClient) {
+        loop {
+            match client.take_token(&self.bucket, self.per_minute, self.burst) {
+                Ok(None) => return,
+                Ok(Some(wait)) => sleep(jitter(wait)).await,
+                Err(e) => {
+                    log::warn!("couldn't take a rate limit token: {:#}", e);
+                    sleep(self.interval()).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Take a token without waiting, returns how long it would take when there is none.
+    /// Without a database there is no token, the caller waits a full interval
+    pub fn try_acquire(&self, client: &mut PostgresClient) -> Option<Duration> {
+        match client.take_token(&self.bucket, self.per_minute, self.burst) {
+            Ok(wait) => wait.map(jitter),
+            Err(e) => {
+                log::warn!("couldn't take a rate limit token: {:#}", e);
+                Some(self.interval())
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    collections::VecDeque,
+    env,
+    time::{Duration, Instant},
+};
+
+use chrono::{TimeDelta, Utc};
+
+use crate::connectors::postgres::lib::PostgresClient;
+
+const DEFAULT_REFRESH_PER_HOUR: u32 = 60;
+const DEFAULT_REFRESH_MIN_AGE_HOURS: i64 = 24;
+/// Stale experiences loaded from the database at once
+const REFRESH_BATCH_SIZE: i64 = 50;
+/// Wait before looking again when nothing was stale
+const EMPTY_RECHECK: Duration = Duration::from_secs(60);
+
+/// Second scheduling lane that revisits stored experiences, next to the discovery of new codes.
+///
+/// Picks the experiences that weren't updated for the longest time, preferring the ones
+/// that were recently edited by their creator, with a budget of refreshes per hour.
+pub struct RefreshScheduler {
+    per_hour: u32,
+    min_age: TimeDelta,
+    tokens: f64,
+    last_refill: Instant,
+    queue: VecDeque<i32>,
+    /// Last time nothing was stale
+    found_none_at: Option<Instant>,
+}
+
+impl RefreshScheduler {
+    /// Read REFRESH_PER_HOUR (0 disables refreshing) and REFRESH_MIN_AGE_HOURS, with defaults
+    pub fn from_env() -> Self {
+        Self {
+            per_hour: env::var("REFRESH_PER_HOUR")
+                .ok()
+                .and_then(|budget| budget.parse().ok())
+                .unwrap_or(DEFAULT_REFRESH_PER_HOUR),
+            min_age: TimeDelta::hours(
+                env::var("REFRESH_MIN_AGE_HOURS")
+                    .ok()
+                    .and_then(|hours| hours.parse().ok())
+                    .unwrap_or(DEFAULT_REFRESH_MIN_AGE_HOURS),
+            ),
+            tokens: 0.0,
+            last_refill: Instant::now(),
+            queue: VecDeque::new(),
+            found_none_at: None,
+        }
+    }
+
+    /// Add the budget gained since the last call, returns whether a refresh is left
+    fn refill(&mut self) -> bool {
+        if self.per_hour == 0 {
+            return false;
+        }
+        let per_second = self.per_hour as f64 / 3600.0;
+        // don't save up more than a minute of budget
+        let capacity = (per_second * 60.0).max(1.0);
+        self.tokens =
+            (self.tokens + self.last_refill.elapsed().as_secs_f64() * per_second).min(capacity);
+        self.last_refill = Instant::now();
+        self.tokens >= 1.0
+    }
+
+    /// Next experience to refresh, None if the budget is used up or nothing is stale.
+    /// The budget is only spent when there is an experience to refresh
+    pub fn next(&mut self, client: &mut PostgresClient) -> anyhow::Result<Option<i32>> {
+        if !self.refill() {
+            return Ok(None);
+        }
+        let recently_empty = self
+            .found_none_at
+            .is_some_and(|at| at.elapsed() < EMPTY_RECHECK);
+        if self.queue.is_empty() && !recently_empty {
+            let older_than = Utc::now().naive_utc() - self.min_age;
+            self.queue
+                .extend(client.stale_experiences(older_than, REFRESH_BATCH_SIZE)?);
+            self.found_none_at = self.queue.is_empty().then(Instant::now);
+        }
+        let next = self.queue.pop_front();
+        if next.is_some() {
+            self.tokens -= 1.0;
+        }
+        Ok(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connectors::health::Health;
+
+    use super::*;
+
+    fn scheduler(per_hour: u32, queue: Vec<i32>) -> RefreshScheduler {
+        RefreshScheduler {
+            per_hour,
+            queue: queue.into(),
+            ..RefreshScheduler::from_env()
+        }
+    }
+
+    #[test]
+    fn budget_builds_up_over_time() {
+        let mut refresh = scheduler(3600, vec![]);
+        assert!(!refresh.refill());
+
+        refresh.last_refill = Instant::now() - Duration::from_secs(10);
+        assert!(refresh.refill());
+        assert!((refresh.tokens - 10.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn budget_is_capped_at_a_minute() {
+        let mut refresh = scheduler(3600, vec![]);
+        refresh.last_refill = Instant::now() - Duration::from_secs(3600);
+        refresh.refill();
+        assert_eq!(refresh.tokens, 60.0);
+
+        // slow budgets can still save up a single refresh
+        let mut refresh = scheduler(6, vec![]);
+        refresh.last_refill = Instant::now() - Duration::from_secs(3600);
+        refresh.refill();
+        assert_eq!(refresh.tokens, 1.0);
+    }
+
+    #[test]
+    fn disabled_without_budget() {
+        let mut refresh = scheduler(0, vec![1]);
+        refresh.last_refill = Instant::now() - Duration::from_secs(3600);
+        let mut client = PostgresClient::unreachable(&Health::new());
+        assert_eq!(refresh.next(&mut client).unwrap(), None);
+    }
+
+    #[test]
+    fn budget_is_spent_per_refresh() {
+        let mut refresh = scheduler(60, vec![5, 6]);
+        refresh.tokens = 1.0;
+        let mut client = PostgresClient::unreachable(&Health::new());
+
+        assert_eq!(refresh.next(&mut client).unwrap(), Some(5));
+        assert_eq!(refresh.next(&mut client).unwrap(), None);
+        assert_eq!(refresh.queue, [6]);
+    }
+
+    #[test]
+    fn nothing_stale_keeps_the_budget() {
+        let mut refresh = scheduler(60, vec![]);
+        refresh.tokens = 1.0;
+        refresh.found_none_at = Some(Instant::now());
+        let mut client = PostgresClient::unreachable(&Health::new());
+
+        assert_eq!(refresh.next(&mut client).unwrap(), None);
+        assert!(refresh.tokens >= 1.0);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{env, time::Duration};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_BASE_DELAY_SECS: u64 = 30;
+/// Never wait longer than this between attempts
+const MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Exponential backoff for experience codes that failed to fetch
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Read RETRY_MAX_ATTEMPTS and RETRY_BASE_DELAY_SECS, with defaults
+    pub fn from_env() -> Self {
+        Self {
+            max_attempts: env::var("RETRY_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|attempts| attempts.parse().ok())
+                .unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            base_delay: Duration::from_secs(
+                env::var("RETRY_BASE_DELAY_SECS")
+                    .ok()
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(DEFAULT_BASE_DELAY_SECS),
+            ),
+        }
+    }
+
+    /// Delay before the next attempt after `failed_attempts` failures,
+    /// None if the code should be dead-lettered
+    pub fn delay(&self, failed_attempts: u32) -> Option<Duration> {
+        if failed_attempts == 0 || failed_attempts >= self.max_attempts {
+            return None;
+        }
+        let delay = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(failed_attempts - 1));
+        Some(delay.min(MAX_DELAY))
+    }
+
+    /// Delay after `failed_attempts` throttled or rejected attempts. Those aren't the code's
+    /// fault and are never dead-lettered, the delay stops growing at the longest retry queue
+    pub fn backoff(&self, failed_attempts: u32) -> Duration {
+        let longest = self.max_attempts.saturating_sub(1).max(1);
+        self.delay(failed_attempts.clamp(1, longest))
+            .unwrap_or(self.base_delay)
+    }
+
+    /// All delays that can be returned, used to declare the retry queues
+    pub fn delays(&self) -> Vec<Duration> {
+        let mut delays: Vec<Duration> = (1..self.max_attempts)
+            .filter_map(|attempt| self.delay(attempt))
+            .collect();
+        delays.dedup();
+        delays
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_attempts: u32, base_delay_secs: u64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_secs(base_delay_secs),
+        }
+    }
+
+    #[test]
+    fn delay_doubles_until_attempts_run_out() {
+        let policy = policy(4, 30);
+        assert_eq!(policy.delay(0), None);
+        assert_eq!(policy.delay(1), Some(Duration::from_secs(30)));
+        assert_eq!(policy.delay(2), Some(Duration::from_secs(60)));
+        assert_eq!(policy.delay(3), Some(Duration::from_secs(120)));
+        assert_eq!(policy.delay(4), None);
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let policy = policy(10, 1800);
+        assert_eq!(policy.delay(2), Some(MAX_DELAY));
+        assert_eq!(policy.delay(9), Some(MAX_DELAY));
+        assert_eq!(policy.delays(), [Duration::from_secs(1800), MAX_DELAY]);
+
+        // no overflow on absurd attempts
+        assert_eq!(
+            RetryPolicy {
+                max_attempts: u32::MAX,
+                ..policy
+            }
+            .delay(100),
+            Some(MAX_DELAY)
+        );
+    }
+
+    #[test]
+    fn backoff_never_gives_up() {
+        let policy = policy(3, 10);
+        assert_eq!(policy.backoff(1), Duration::from_secs(10));
+        assert_eq!(policy.backoff(2), Duration::from_secs(20));
+        assert_eq!(policy.backoff(50), Duration::from_secs(20));
+        assert!(policy.delays().contains(&policy.backoff(50)));
+
+        assert_eq!(self::policy(1, 10).backoff(5), Duration::from_secs(10));
+    }
+}
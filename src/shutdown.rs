@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+/// Flips once SIGINT or SIGTERM is received, so loops can finish their current work and exit
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Start listening for the signals, has to be called from within the runtime
+    pub fn listen() -> anyhow::Result<Self> {
+        let (sender, receiver) = watch::channel(false);
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {},
+                _ = terminate.recv() => {},
+            }
+            log::info!("Shutdown requested, finishing current work...");
+            let _ = sender.send(true);
+        });
+        Ok(Self { receiver })
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once shutdown is requested
+    pub async fn requested(&mut self) {
+        // the sender only goes away after it sent true
+        let _ = self.receiver.wait_for(|requested| *requested).await;
+    }
+
+    /// Sleep that is cut short by a shutdown, returns false if it was
+    pub async fn sleep(&mut self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => true,
+            _ = self.requested() => false,
+        }
+    }
+}
@@ -0,0 +1,574 @@
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use futures::stream::StreamExt;
+use uuid::Uuid;
+
+use crate::{
+    clients::playground_source::{self, PlaygroundSource},
+    connectors::{
+        health::{Backoff, Health},
+        mongo::lib::MongoClient,
+        postgres::{lib::PostgresClient, models::CrawlOutcome},
+        transport::{lib::Transport, rabbit::RabbitTransport},
+    },
+    error::{self, ExplorerError},
+    experience_code::ExperienceCode,
+    frontier::Frontier,
+    messages::{self, Heartbeat, JobKind, WorkItem, WorkResult, WorkerMessage, HEARTBEAT_INTERVAL},
+    rate_limit::RateLimiter,
+    retry::RetryPolicy,
+    shutdown::Shutdown,
+};
+
+/// Codes leased from Postgres at once
+const DEFAULT_LEASE_SIZE: i32 = 100;
+/// Leases that aren't renewed within this time can be taken over by other workers
+const LEASE_DURATION: Duration = Duration::from_secs(120);
+
+/// Where the worker gets its codes from, set with QUEUE_BACKEND
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueBackend {
+    /// Work items handed out by rabbit_host
+    RabbitMq,
+    /// Ranges of codes leased from the crawl_leases table, no host needed
+    Postgres,
+    /// rabbit_host runs MEMORY_WORKERS workers itself, on in-process queues
+    Memory,
+}
+
+impl QueueBackend {
+    pub fn from_env() -> Result<Self> {
+        match env::var("QUEUE_BACKEND").unwrap_or_default().as_str() {
+            "" | "rabbitmq" => Ok(QueueBackend::RabbitMq),
+            "postgres" => Ok(QueueBackend::Postgres),
+            "memory" => Ok(QueueBackend::Memory),
+            other => anyhow::bail!("unknown QUEUE_BACKEND {}", other),
+        }
+    }
+}
+
+pub(crate) struct FunctionWorker {
+    /// Where experiences are fetched from, set with PLAYGROUND_SOURCES
+    pub source: Box<dyn PlaygroundSource>,
+    pub db_client: PostgresClient,
+    /// Queue transport to the host, only used with the rabbitmq backend
+    pub transport: Option<Arc<dyn Transport>>,
+    /// Uniq Worker ID
+    pub uuid: String,
+    /// Healthcheck state, touched after every successful check
+    health: Health,
+    /// Request budget shared with the rest of the fleet
+    rate_limiter: RateLimiter,
+    /// Retries of failed codes with the postgres backend, the host retries them otherwise
+    retry_policy: RetryPolicy,
+    /// Current assignment and counters, sent to the host periodically
+    heartbeat: Arc<Mutex<Heartbeat>>,
+}
+
+impl FunctionWorker {
+    /**
+        Create a new Fortress Node
+        Returns Self
+    */
+    pub async fn new(health: Health) -> Result<Self> {
+        let transport: Option<Arc<dyn Transport>> = match QueueBackend::from_env()? {
+            QueueBackend::RabbitMq => Some(Arc::new(RabbitTransport::connect(&health).await?)),
+            QueueBackend::Postgres => None,
+            QueueBackend::Memory => {
+                anyhow::bail!("QUEUE_BACKEND=memory runs the workers inside rabbit_host")
+            }
+        };
+        Self::with_transport(transport, health).await
+    }
+
+    /// Create a worker on any queue transport, like the in-memory one.
+    /// Without a transport the worker leases its codes from Postgres
+    pub async fn with_transport(
+        transport: Option<Arc<dyn Transport>>,
+        health: Health,
+    ) -> Result<Self> {
+        let uuid = Uuid::new_v4().to_string();
+        let db_client = PostgresClient::connect(&health)?;
+        let mongo = MongoClient::connect(&health).await?;
+        Ok(Self {
+            source: playground_source::connect(&health, &uuid, mongo, db_client.clone()).await?,
+            db_client,
+            transport,
+            heartbeat: Arc::new(Mutex::new(Heartbeat {
+                worker: uuid.clone(),
+                assignment: None,
+                processed: 0,
+                errors: 0,
+                errors_by_kind: Default::default(),
+            })),
+            uuid,
+            health,
+            rate_limiter: RateLimiter::from_env(),
+            retry_policy: RetryPolicy::from_env(),
+        })
+    }
+
+    /// Let the host know we're alive and what we're working on
+    fn spawn_heartbeat(&self, transport: &Arc<dyn Transport>) {
+        let transport = Arc::clone(transport);
+        let heartbeat = Arc::clone(&self.heartbeat);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                interval.tick().await;
+                let message = heartbeat.lock().unwrap().clone();
+                let message = match messages::encode(WorkerMessage::Heartbeat(message)) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        log::warn!("couldn't encode heartbeat: {:#}", e);
+                        continue;
+                    }
+                };
+                if let Err(e) = transport
+                    .publish("experience_workerstatuscollector-v1", message)
+                    .await
+                {
+                    log::warn!("couldn't send heartbeat: {:#}", e);
+                }
+            }
+        });
+    }
+
+    /// Init all needed ques
+    async fn init_ques(&mut self, transport: &Arc<dyn Transport>) -> Result<()> {
+        let ques = ["experience_code-v1", "workerstatuscollector-v4"];
+
+        for que in ques.iter() {
+            transport.declare_que(que).await?;
+        }
+
+        transport.declare_que_worker(&self.uuid).await?;
+
+        Ok(())
+    }
+
+    /// Run Fortress node
+    pub async fn run(&mut self) -> Result<()> {
+        let mut shutdown = Shutdown::listen()?;
+
+        match self.transport.clone() {
+            Some(transport) => {
+                self.spawn_heartbeat(&transport);
+                let mut backoff = Backoff::new();
+                while !shutdown.is_requested() {
+                    log::info!("Emitting Ques");
+                    let result = match self.init_ques(&transport).await {
+                        Ok(_) => {
+                            log::info!("Running {} fortress node", &self.uuid);
+                            self.run_loop(&transport, &mut shutdown).await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    // the broker is down, stay up with a degraded health until it's back
+                    match result {
+                        Ok(_) => backoff.reset(),
+                        Err(e) => {
+                            self.health.degrade("rabbitmq", &e);
+                            let delay = backoff.advance();
+                            log::warn!("queues failed, retrying in {:?}: {:#}", delay, e);
+                            shutdown.sleep(delay).await;
+                        }
+                    }
+                }
+
+                // the current item is done, anything not acked yet goes back to the queue on close
+                log::info!("Stopped consuming, leaving");
+                transport
+                    .publish(
+                        "experience_workerstatuscollector-v1",
+                        messages::encode(WorkerMessage::Shutdown {
+                            worker: self.uuid.clone(),
+                        })?,
+                    )
+                    .await?;
+                transport.close().await?;
+            }
+            None => {
+                log::info!("Running {} fortress node on postgres leases", &self.uuid);
+                self.run_lease_loop(&mut shutdown).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Work through ranges of codes leased from Postgres.
+    ///
+    /// Once this worker walked past the newest experience it stops leasing new ranges
+    /// and polls just past the frontier, like the standalone crawler
+    async fn run_lease_loop(&mut self, shutdown: &mut Shutdown) -> Result<()> {
+        let lease_size = env::var("LEASE_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_LEASE_SIZE);
+
+        let mut backoff = Backoff::new();
+        let cursor = loop {
+            match self.db_client.current_experience() {
+                Ok(cursor) => break cursor,
+                Err(e) => {
+                    let delay = backoff.advance();
+                    log::warn!("couldn't load the cursor, retrying in {:?}: {:#}", delay, e);
+                    if !shutdown.sleep(delay).await {
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        let mut frontier = Frontier::from_env(cursor);
+
+        while !shutdown.is_requested() {
+            if let Some(code) = frontier.probe() {
+                if frontier.is_tailing() && !shutdown.sleep(frontier.tail_poll()).await {
+                    break;
+                }
+                let found = match self.process_with_retries(code, None, shutdown).await {
+                    Some(Some(found)) => found,
+                    Some(None) => {
+                        frontier.abandon_probe();
+                        continue;
+                    }
+                    None => break,
+                };
+                match frontier.observe_probe(code, found) {
+                    // new experiences past the frontier, lease the codes after it again
+                    Some(restart) if !frontier.is_tailing() => {
+                        if let Err(e) = self.db_client.restart_leases(restart) {
+                            log::warn!("couldn't restart the leases at {}: {:#}", restart, e);
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            let lease = match self
+                .db_client
+                .lease_range(&self.uuid, LEASE_DURATION, lease_size)
+            {
+                Ok(lease) => lease,
+                Err(e) => {
+                    let delay = backoff.advance();
+                    log::warn!("couldn't lease codes, retrying in {:?}: {:#}", delay, e);
+                    shutdown.sleep(delay).await;
+                    continue;
+                }
+            };
+            backoff.reset();
+            log::info!(
+                "Leased {} until {}, starting at {}",
+                lease.range_start,
+                lease.range_end,
+                lease.next_code
+            );
+
+            let mut lost = false;
+            let mut finished = true;
+            for code in lease.next_code..lease.range_end {
+                if shutdown.is_requested() {
+                    finished = false;
+                    break;
+                }
+                let found = match self
+                    .process_with_retries(code, Some(lease.range_start), shutdown)
+                    .await
+                {
+                    Some(found) => found,
+                    None => {
+                        lost = !shutdown.is_requested();
+                        finished = false;
+                        break;
+                    }
+                };
+                if let Some(found) = found {
+                    frontier.observe(code, found);
+                }
+
+                match self.db_client.renew_lease(
+                    lease.range_start,
+                    &self.uuid,
+                    code + 1,
+                    LEASE_DURATION,
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        log::warn!("lease {} was taken over", lease.range_start);
+                        lost = true;
+                        break;
+                    }
+                    // the lease expires and is picked up again once the database is back
+                    Err(e) => {
+                        log::warn!("couldn't renew lease {}: {:#}", lease.range_start, e);
+                        lost = true;
+                        break;
+                    }
+                }
+
+                // looking for the frontier, the rest of the range is left to a worker that still crawls
+                if frontier.probe().is_some() {
+                    finished = false;
+                    break;
+                }
+            }
+
+            if lost {
+                continue;
+            }
+            if finished {
+                if let Err(e) = self.db_client.complete_lease(lease.range_start, &self.uuid) {
+                    log::warn!("couldn't complete lease {}: {:#}", lease.range_start, e);
+                }
+            } else {
+                // progress is saved, let another worker continue without waiting for the lease to expire
+                match self.db_client.release_lease(lease.range_start, &self.uuid) {
+                    Ok(_) => log::info!("Released lease {}", lease.range_start),
+                    Err(e) => log::warn!("couldn't release lease {}: {:#}", lease.range_start, e),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check a code until it's found or not, retrying failures with the retry policy
+    /// and moving the code to the dead letters once they ran out, which gives Some(None).
+    /// Throttled and rejected attempts back off and are retried until they go through.
+    ///
+    /// `lease` is kept while waiting for a retry. Returns None when shutdown was requested
+    /// or the lease was lost in the meantime
+    async fn process_with_retries(
+        &mut self,
+        code: i32,
+        lease: Option<i32>,
+        shutdown: &mut Shutdown,
+    ) -> Option<Option<bool>> {
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .process(&WorkItem {
+                    code,
+                    attempt,
+                    kind: JobKind::Discover,
+                })
+                .await;
+            let error = match result.error {
+                None | Some(ExplorerError::NotFound) => {
+                    return Some(Some(result.outcome == CrawlOutcome::Found))
+                }
+                Some(error) => error,
+            };
+
+            // errors that won't go away go to the dead letters right away
+            let delay = match error {
+                ExplorerError::Throttled(_) | ExplorerError::Unauthorized(_) => {
+                    Some(self.retry_policy.backoff(attempt))
+                }
+                _ if error.retryable() => self.retry_policy.delay(attempt),
+                _ => None,
+            };
+            let delay = match delay {
+                Some(delay) => delay,
+                None => {
+                    log::error!(
+                        "{} failed {} times, moving it to the dead letters",
+                        code,
+                        attempt
+                    );
+                    if let Err(e) = self.db_client.add_dead_letter(
+                        code,
+                        attempt as i32,
+                        Some(error.to_string()),
+                    ) {
+                        log::error!("couldn't store dead letter for {}: {:#}", code, e);
+                    }
+                    return Some(None);
+                }
+            };
+            log::warn!(
+                "{} failed {} times, retrying in {:?}: {}",
+                code,
+                attempt,
+                delay,
+                error
+            );
+
+            if let Some(range_start) = lease {
+                match self.db_client.renew_lease(
+                    range_start,
+                    &self.uuid,
+                    code,
+                    LEASE_DURATION + delay,
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        log::warn!("lease {} was taken over", range_start);
+                        return None;
+                    }
+                    Err(e) => {
+                        log::warn!("couldn't renew lease {}: {:#}", range_start, e);
+                        return None;
+                    }
+                }
+            }
+            if !shutdown.sleep(delay).await {
+                return None;
+            }
+            attempt += 1;
+        }
+    }
+
+    async fn run_loop(
+        &mut self,
+        transport: &Arc<dyn Transport>,
+        shutdown: &mut Shutdown,
+    ) -> Result<()> {
+        transport
+            .publish(
+                "experience_workerstatuscollector-v1",
+                messages::encode(WorkerMessage::Startup {
+                    worker: self.uuid.clone(),
+                })?,
+            )
+            .await?;
+
+        transport.set_qos(1).await?;
+        let consumer = transport.consume("experience_code-v1").await?;
+        let mut iter = consumer;
+        self.health.recover("rabbitmq");
+
+        // For each new group
+        loop {
+            let next_task = tokio::select! {
+                next_task = iter.next() => next_task,
+                _ = shutdown.requested() => break,
+            };
+            // Get delivery, the stream ends when the connection dropped
+            let delivery = match next_task {
+                Some(Ok(delivery)) => delivery,
+                Some(Err(e)) => {
+                    log::warn!("work consumer failed: {:#}", e);
+                    break;
+                }
+                None => break,
+            };
+
+            let item: WorkItem = match messages::decode(&delivery.data) {
+                Ok(item) => item,
+                Err(e) => {
+                    log::error!("rejecting malformed work item: {:#}", e);
+                    delivery.nack(false).await?;
+                    continue;
+                }
+            };
+
+            let result = self.process(&item).await;
+
+            // Ack delivery, without a connection it's redelivered and checked again
+            if let Err(e) = delivery.ack().await {
+                log::warn!("couldn't ack {}: {:#}", item.code, e);
+                break;
+            }
+
+            // Send a response
+            if let Err(e) = transport
+                .publish(
+                    "experience_workerstatuscollector-v1",
+                    messages::encode(WorkerMessage::Result(result))?,
+                )
+                .await
+            {
+                log::warn!("couldn't send the result of {}: {:#}", item.code, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check a single work item and write the attempt to the crawl ledger
+    async fn process(&mut self, item: &WorkItem) -> WorkResult {
+        log::info!("Assigned {} ({:?})", item.code, item.kind);
+        self.heartbeat.lock().unwrap().assignment = Some(item.clone());
+        let started = Instant::now();
+
+        let result = match ExperienceCode::from_i32(item.code) {
+            Ok(e_code) => self.check_experience(&e_code).await,
+            Err(e) => Err(e.into()),
+        };
+
+        let (outcome, error) = match &result {
+            Ok(true) => (CrawlOutcome::Found, None),
+            Ok(false) => (CrawlOutcome::NotFound, None),
+            Err(e) => {
+                log::error!("{} failed: {}", item.code, e);
+                if let ExplorerError::Throttled(_) = e {
+                    self.rate_limiter.throttled(&mut self.db_client);
+                }
+                (e.outcome(), Some(e.clone()))
+            }
+        };
+        if let Err(e) =
+            self.db_client
+                .record_attempt(item.code, outcome, error.as_ref().map(|e| e.to_string()))
+        {
+            log::warn!("couldn't update crawl ledger for {}: {:#}", item.code, e);
+        }
+
+        {
+            let mut heartbeat = self.heartbeat.lock().unwrap();
+            heartbeat.assignment = None;
+            heartbeat.processed += 1;
+            if let Err(e) = &result {
+                heartbeat.errors += 1;
+                *heartbeat
+                    .errors_by_kind
+                    .entry(e.label().to_string())
+                    .or_default() += 1;
+            }
+        }
+
+        if result.is_ok() {
+            // healthcheck
+            self.health.touch();
+        }
+
+        WorkResult {
+            worker: self.uuid.clone(),
+            code: item.code,
+            attempt: item.attempt,
+            kind: item.kind,
+            outcome,
+            duration_ms: started.elapsed().as_millis() as u64,
+            error,
+        }
+    }
+
+    /// Fetch and store the experience, returns false if the code has no playground
+    async fn check_experience(&mut self, e_code: &ExperienceCode) -> error::Result<bool> {
+        // don't go to fast, otherwise you will get temporarily blocked.
+        self.rate_limiter.acquire(&mut self.db_client).await;
+        let experience = self.source.fetch(e_code).await?;
+        self.rate_limiter.succeeded(&mut self.db_client);
+
+        if let Some(experience) = experience {
+            log::info!("gathered experience: {}", experience.playground_name);
+            self.db_client.add_or_update_experience(experience)?;
+            return Ok(true);
+        }
+
+        let code = e_code.to_usize()? as i32;
+        if self.db_client.mark_experience_deleted(code)? {
+            log::info!("{} no longer exists, marked as deleted", code);
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::messages::{Heartbeat, WorkItem};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerInfo {
+    pub worker: String,
+    /// Unix timestamp of the last message from the worker
+    pub last_seen: i64,
+    pub assignment: Option<WorkItem>,
+    pub processed: u64,
+    pub errors: u64,
+    /// Errors by their label
+    pub errors_by_kind: BTreeMap<String, u64>,
+}
+
+/// Workers known to the host, kept up to date with their heartbeats
+pub struct WorkerRegistry {
+    workers: HashMap<String, WorkerInfo>,
+    timeout: Duration,
+}
+
+impl WorkerRegistry {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            workers: HashMap::new(),
+            timeout,
+        }
+    }
+
+    fn entry(&mut self, worker: &str) -> &mut WorkerInfo {
+        let info = self
+            .workers
+            .entry(worker.to_string())
+            .or_insert_with(|| WorkerInfo {
+                worker: worker.to_string(),
+                last_seen: 0,
+                assignment: None,
+                processed: 0,
+                errors: 0,
+                errors_by_kind: BTreeMap::new(),
+            });
+        info.last_seen = Utc::now().timestamp();
+        info
+    }
+
+    /// Any message of the worker counts as a sign of life
+    pub fn seen(&mut self, worker: &str) {
+        self.entry(worker);
+    }
+
+    pub fn heartbeat(&mut self, heartbeat: Heartbeat) {
+        let info = self.entry(&heartbeat.worker);
+        info.assignment = heartbeat.assignment;
+        info.processed = heartbeat.processed;
+        info.errors = heartbeat.errors;
+        info.errors_by_kind = heartbeat.errors_by_kind;
+    }
+
+    /// The worker sent the result of its assignment
+    pub fn finished(&mut self, worker: &str, code: i32) {
+        let info = self.entry(worker);
+        if info.assignment.as_ref().map(|item| item.code) == Some(code) {
+            info.assignment = None;
+        }
+    }
+
+    /// Forget a worker that shut down, returns what it was still working on
+    pub fn left(&mut self, worker: &str) -> Option<WorkItem> {
+        self.workers.remove(worker).and_then(|info| info.assignment)
+    }
+
+    /// Forget workers that went silent, returns what they were working on
+    pub fn reap(&mut self) -> Vec<(String, WorkItem)> {
+        let deadline = Utc::now().timestamp() - self.timeout.as_secs() as i64;
+        let silent: Vec<String> = self
+            .workers
+            .values()
+            .filter(|info| info.last_seen < deadline)
+            .map(|info| info.worker.clone())
+            .collect();
+
+        let mut orphaned = vec![];
+        for worker in silent {
+            if let Some(info) = self.workers.remove(&worker) {
+                log::warn!("worker {} went silent", worker);
+                if let Some(item) = info.assignment {
+                    orphaned.push((worker, item));
+                }
+            }
+        }
+        orphaned
+    }
+
+    pub fn workers(&self) -> Vec<WorkerInfo> {
+        let mut workers: Vec<WorkerInfo> = self.workers.values().cloned().collect();
+        workers.sort_by(|a, b| a.worker.cmp(&b.worker));
+        workers
+    }
+}